@@ -0,0 +1,300 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
+
+// The socket of the running agent, like `SSH_AUTH_SOCK` for ssh-agent.
+pub const SOCKET_VARIABLE: &str = "ENVM_AGENT_SOCK";
+
+pub const PASSPHRASE: &str = "passphrase";
+pub const IDENTITY: &str = "identity";
+
+// The time to wait for the request of a connection, so a client which connects and never writes
+// doesn't keep its thread forever.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The passphrase is shared by the environments of a repository only, so it's kept per
+// repository.
+pub fn passphrase_name(repository: &Path) -> String {
+    format!("{}:{}", PASSPHRASE, repository.display())
+}
+
+// The keys held by the agent with the time they were added, each key is forgotten after the
+// lifetime.
+struct Keys {
+    values: HashMap<String, (String, Instant)>,
+    lifetime: Duration,
+}
+
+impl Keys {
+    fn expire(&mut self) {
+        let lifetime = self.lifetime;
+        self.values
+            .retain(|_, (_, added)| added.elapsed() < lifetime);
+    }
+
+    // Handle a request line, return the response and whether the agent should stop.
+    //
+    // The requests are `get <name>`, `set <name> <base64 value>`, `forget <name>`, `lock` which
+    // forgets all keys and `stop`. The responses are `ok`, `ok <base64 value>`, `none` or
+    // `error <message>`. The name is the rest of the line, since it can be a path with spaces.
+    fn handle(&mut self, request: &str) -> (String, bool) {
+        self.expire();
+        let request = request.trim();
+        let (command, name) = request.split_once(' ').unwrap_or((request, ""));
+        let response = match (command, name) {
+            ("get", name) if !name.is_empty() => match self.values.get(name) {
+                Some((value, _)) => format!("ok {}", STANDARD.encode(value)),
+                None => String::from("none"),
+            },
+            ("set", rest) => match rest.rsplit_once(' ') {
+                Some((name, value)) if !name.is_empty() => match decode(value) {
+                    Ok(value) => {
+                        self.values
+                            .insert(name.to_string(), (value, Instant::now()));
+                        String::from("ok")
+                    }
+                    Err(err) => format!("error {}", err),
+                },
+                _ => format!("error invalid request `{}`", request),
+            },
+            ("forget", name) if !name.is_empty() => {
+                self.values.remove(name);
+                String::from("ok")
+            }
+            ("lock", "") => {
+                self.values.clear();
+                String::from("ok")
+            }
+            ("stop", "") => {
+                self.values.clear();
+                return (String::from("ok"), true);
+            }
+            _ => format!("error invalid request `{}`", request),
+        };
+        (response, false)
+    }
+}
+
+// A new socket path in a directory only the user can access.
+pub fn socket_path() -> Result<PathBuf, String> {
+    let mut random = [0u8; 8];
+    getrandom::getrandom(&mut random).map_err(|err| err.to_string())?;
+    let name: String = random.iter().map(|it| format!("{:02x}", it)).collect();
+    let dir = env::temp_dir().join(format!("envm-{}", name));
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|err| format!("cannot create {}: {}", dir.display(), err))?;
+    Ok(dir.join("agent.sock"))
+}
+
+// Serve on the socket until `stop`, the keys are kept in memory only and forgotten after the
+// lifetime.
+pub fn serve(socket: &Path, lifetime: Duration) -> Result<(), String> {
+    let listener = UnixListener::bind(socket)
+        .map_err(|err| format!("cannot listen on {}: {}", socket.display(), err))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+        .map_err(|err| err.to_string())?;
+    let keys = Arc::new(Mutex::new(Keys {
+        values: HashMap::new(),
+        lifetime,
+    }));
+
+    let expiring = Arc::clone(&keys);
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(1));
+        expiring.lock().unwrap().expire();
+    });
+
+    // Each connection is handled in its own thread, so a slow client doesn't block the others.
+    let stopping = Arc::new(AtomicBool::new(false));
+    for stream in listener.incoming() {
+        if stopping.load(Ordering::SeqCst) {
+            break;
+        }
+        let stream = match stream {
+            Ok(it) => it,
+            Err(_) => continue,
+        };
+        let keys = Arc::clone(&keys);
+        let stopping = Arc::clone(&stopping);
+        let socket = socket.to_path_buf();
+        thread::spawn(move || {
+            if handle_connection(stream, &keys) {
+                stopping.store(true, Ordering::SeqCst);
+                // Wake up the loop waiting for the next connection, so it sees the stop.
+                let _ = UnixStream::connect(socket);
+            }
+        });
+    }
+    let _ = fs::remove_file(socket);
+    if let Some(dir) = socket.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+    Ok(())
+}
+
+// Answer the request of the connection, return whether the agent should stop.
+fn handle_connection(mut stream: UnixStream, keys: &Mutex<Keys>) -> bool {
+    if stream.set_read_timeout(Some(REQUEST_TIMEOUT)).is_err()
+        || stream.set_write_timeout(Some(REQUEST_TIMEOUT)).is_err()
+    {
+        return false;
+    }
+    let mut request = String::new();
+    if BufReader::new(&stream).read_line(&mut request).is_err() {
+        return false;
+    }
+    let (response, stop) = keys.lock().unwrap().handle(&request);
+    let _ = writeln!(stream, "{}", response);
+    stop
+}
+
+// Talk to the agent at the socket in `ENVM_AGENT_SOCK`.
+pub struct Client {
+    socket: PathBuf,
+}
+
+impl Client {
+    pub fn new(socket: &Path) -> Client {
+        Client {
+            socket: socket.to_path_buf(),
+        }
+    }
+
+    pub fn from_env() -> Option<Client> {
+        env::var(SOCKET_VARIABLE)
+            .ok()
+            .filter(|it| !it.is_empty())
+            .map(|it| Client::new(Path::new(&it)))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<String>, String> {
+        let response = self.request(&format!("get {}", name))?;
+        match response.strip_prefix("ok ") {
+            Some(value) => decode(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<(), String> {
+        self.request(&format!("set {} {}", name, STANDARD.encode(value)))
+            .map(|_| ())
+    }
+
+    pub fn forget(&self, name: &str) -> Result<(), String> {
+        self.request(&format!("forget {}", name)).map(|_| ())
+    }
+
+    pub fn lock(&self) -> Result<(), String> {
+        self.request("lock").map(|_| ())
+    }
+
+    pub fn stop(&self) -> Result<(), String> {
+        self.request("stop").map(|_| ())
+    }
+
+    fn request(&self, request: &str) -> Result<String, String> {
+        let mut stream = UnixStream::connect(&self.socket)
+            .map_err(|err| format!("cannot connect to {}: {}", self.socket.display(), err))?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .map_err(|err| err.to_string())?;
+        writeln!(stream, "{}", request).map_err(|err| err.to_string())?;
+        let mut response = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut response)
+            .map_err(|err| err.to_string())?;
+        let response = response.trim().to_string();
+        match response.strip_prefix("error ") {
+            Some(err) => Err(err.to_string()),
+            None => Ok(response),
+        }
+    }
+}
+
+fn decode(value: &str) -> Result<String, String> {
+    STANDARD
+        .decode(value.trim())
+        .ok()
+        .and_then(|it| String::from_utf8(it).ok())
+        .ok_or_else(|| String::from("invalid value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_handle_requests() {
+        let mut keys = Keys {
+            values: HashMap::new(),
+            lifetime: Duration::from_secs(60),
+        };
+        let value = STANDARD.encode("correct horse");
+
+        assert_eq!(keys.handle("get passphrase").0, "none");
+        assert_eq!(keys.handle(&format!("set passphrase {}", value)).0, "ok");
+        assert_eq!(keys.handle("get passphrase\n").0, format!("ok {}", value));
+        assert_eq!(keys.handle("forget passphrase").0, "ok");
+        assert_eq!(keys.handle("get passphrase").0, "none");
+        assert_eq!(
+            keys.handle(&format!("set passphrase:/a b {}", value)).0,
+            "ok"
+        );
+        assert_eq!(
+            keys.handle("get passphrase:/a b").0,
+            format!("ok {}", value)
+        );
+        assert_eq!(keys.handle("lock").0, "ok");
+        assert_eq!(keys.handle("get passphrase:/a b").0, "none");
+        assert!(keys.handle("set passphrase").0.starts_with("error"));
+        assert_eq!(keys.handle("stop"), (String::from("ok"), true));
+    }
+
+    #[test]
+    fn should_forget_keys_after_lifetime() {
+        let mut keys = Keys {
+            values: HashMap::new(),
+            lifetime: Duration::from_millis(0),
+        };
+        keys.handle(&format!("set passphrase {}", STANDARD.encode("x")));
+        assert_eq!(keys.handle("get passphrase").0, "none");
+    }
+
+    #[test]
+    fn should_serve_on_socket() -> Result<(), String> {
+        let socket = socket_path()?;
+        let serving = socket.clone();
+        let server = thread::spawn(move || serve(&serving, Duration::from_secs(60)));
+        while !socket.exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let mode = fs::metadata(socket.parent().unwrap())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // The connection which never sends its request doesn't block the others.
+        let _idle = UnixStream::connect(&socket).map_err(|err| err.to_string())?;
+        let client = Client::new(&socket);
+        assert_eq!(client.get(PASSPHRASE)?, None);
+        client.set(PASSPHRASE, "correct horse")?;
+        assert_eq!(client.get(PASSPHRASE)?, Some(String::from("correct horse")));
+        client.lock()?;
+        assert_eq!(client.get(PASSPHRASE)?, None);
+        client.stop()?;
+        server.join().unwrap()?;
+        assert!(!socket.exists());
+        Ok(())
+    }
+}
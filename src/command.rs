@@ -1,14 +1,46 @@
-use clap::{arg, crate_authors, crate_description, crate_version, App, AppSettings};
+use clap::{arg, crate_authors, crate_description, crate_version, App, AppSettings, ArgMatches};
+
+use crate::repository::search::SearchScope;
 
 pub enum UseCase {
     DiffEnvironment(String),
-    UseEnvironment(String),
+    UseEnvironment(String, bool),
+    UseStage(String, bool),
     NewEnvironment(String),
-    ListEnvironments,
+    ListEnvironments(bool),
     RemoveEnvironment(String),
     ShowCurrentUsingEnvironment,
     InitConfiguration,
     Gitignore,
+    SearchEnvironments(String, SearchScope, bool),
+    SyncEnvironments(Vec<String>, bool),
+    PruneEnvironments(Vec<String>, bool),
+    GenerateTemplate(bool, String),
+    VerifyEnvironments(Vec<String>, bool),
+    ExportSchema,
+    ShowStatus,
+    LintEnvironments(bool),
+    FormatEnvironments(Vec<String>, bool, bool),
+    ScanSecrets,
+    Fingerprint(String, Option<String>),
+    EncryptEnvironment(String, bool),
+    DecryptEnvironment(String),
+    ListRecipients,
+    AddRecipient(String),
+    RemoveRecipient(String),
+    StartAgent(u64, bool, Option<String>),
+    LockAgent,
+    StopAgent,
+    SignEnvironments(Vec<String>),
+    ShowEnvironment(String, bool, bool),
+    ExecEnvironment(String, Vec<String>, bool),
+    ExplainVariable(String, String),
+}
+
+// The global options apply to all subcommands.
+pub struct Options {
+    pub reveal: bool,
+    pub command: String,
 }
 
 pub struct Command<'a> {
@@ -22,15 +54,31 @@ impl<'a> Command<'a> {
             .author(crate_authors!())
             .about(crate_description!())
             .setting(AppSettings::SubcommandRequiredElseHelp)
+            .arg(
+                arg!(--reveal "Show the values of secret variables, it is logged in .envm/audit.log")
+                    .global(true),
+            )
             .subcommand(
                 App::new("diff")
                     .about("List different between target environment file and template environment file")
                     .arg(arg!(<ENV> "The environment to target"))
             )
+            .subcommand(
+                App::new("verify")
+                    .about("Check the environments have all the variables in template, and the required variables are not empty")
+                    .arg(arg!([ENV]... "The environments to target, default to all environments"))
+                    .arg(arg!(--"fail-on-placeholder" "Fail if any variable still holds a placeholder")),
+            )
             .subcommand(
                 App::new("use")
                     .about("Use environment")
-                    .arg(arg!(<ENV> "The environment to target")),
+                    .arg(arg!([ENV] "The environment to target").required_unless_present("stage"))
+                    .arg(
+                        arg!(--stage <STAGE> "Use the stage of every service, which needs {stage} in the pattern")
+                            .required(false)
+                            .conflicts_with("ENV"),
+                    )
+                    .arg(arg!(-f --force "Use the environment even if it fails the verification")),
             )
             .subcommand(App::new("init").about("Create envm repository"))
             .subcommand(
@@ -38,50 +86,367 @@ impl<'a> Command<'a> {
                     .about("Create new environment base on template")
                     .arg(arg!(<ENV> "The environment to target")),
             )
-            .subcommand(App::new("ls").about("List all available environments except local"))
+            .subcommand(
+                App::new("ls")
+                    .about("List all available environments except local")
+                    .arg(arg!(--matrix "List the environments of the named placeholders as a table")),
+            )
             .subcommand(
                 App::new("rm")
                     .about("Remove given environment")
                     .arg(arg!(<ENV> "The environment to target")),
             )
             .subcommand(App::new("now").about("Show current using environment"))
-            .subcommand(App::new("gitignore").about("Ignore .envm on git"));
+            .subcommand(
+                App::new("status")
+                    .about("Show current using environment and the problems compared to template"),
+            )
+            .subcommand(App::new("gitignore").about("Ignore .envm on git"))
+            .subcommand(
+                App::new("grep")
+                    .about("Search variables across all environments, template, local and backup")
+                    .arg(arg!(<PATTERN> "The regular expression to search"))
+                    .arg(arg!(-k --keys "Only match the keys").conflicts_with("values"))
+                    .arg(arg!(-v --values "Only match the values"))
+                    .arg(arg!(--"show-values" "Show the values instead of masking them")),
+            )
+            .subcommand(
+                App::new("sync")
+                    .about("Add the variables missing from template to environments")
+                    .arg(arg!([ENV]... "The environments to target, default to all environments"))
+                    .arg(arg!(--"dry-run" "Show the variables to add without writing")),
+            )
+            .subcommand(
+                App::new("prune")
+                    .about("Remove the variables no longer in template from environments")
+                    .arg(arg!([ENV]... "The environments to target, default to all environments"))
+                    .arg(arg!(--"dry-run" "Show the variables to remove without writing")),
+            )
+            .subcommand(
+                App::new("lint")
+                    .about("Check the template, local and environment files for common mistakes")
+                    .arg(arg!(--fix "Fix the problems which can be fixed automatically")),
+            )
+            .subcommand(
+                App::new("scan")
+                    .about("Check the environments for reused production secrets and live credentials"),
+            )
+            .subcommand(
+                App::new("encrypt")
+                    .about("Encrypt the environment file with a passphrase")
+                    .arg(arg!(<ENV> "The environment to target"))
+                    .arg(arg!(--values "Encrypt each value and keep the keys in plaintext")),
+            )
+            .subcommand(
+                App::new("decrypt")
+                    .about("Decrypt the environment file back to plaintext")
+                    .arg(arg!(<ENV> "The environment to target")),
+            )
+            .subcommand(
+                App::new("recipients")
+                    .about("Manage the public keys which the environments are encrypted to")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(App::new("ls").about("List the recipients"))
+                    .subcommand(
+                        App::new("add")
+                            .about("Add a recipient and encrypt the environments again")
+                            .arg(arg!(<KEY> "The age X25519 public key, starts with age1")),
+                    )
+                    .subcommand(
+                        App::new("rm")
+                            .about("Remove a recipient and encrypt the environments again")
+                            .arg(arg!(<KEY> "The age X25519 public key, starts with age1")),
+                    ),
+            )
+            .subcommand(
+                App::new("agent")
+                    .about("Keep the unlocked keys for a session, like ssh-agent")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        App::new("start")
+                            .about("Start the agent and print the variable pointing at its socket")
+                            .arg(
+                                arg!(--lifetime <SECONDS> "Forget the keys after the seconds")
+                                    .required(false)
+                                    .default_value("3600")
+                                    .validator(|it| it.parse::<u64>()),
+                            )
+                            .arg(arg!(--foreground "Run in the foreground instead of detaching"))
+                            .arg(
+                                arg!(--socket <PATH> "The socket to listen on")
+                                    .required(false)
+                                    .hide(true),
+                            ),
+                    )
+                    .subcommand(App::new("lock").about("Forget the keys held by the agent"))
+                    .subcommand(App::new("stop").about("Stop the agent")),
+            )
+            .subcommand(
+                App::new("fingerprint")
+                    .about("Print the salted hashes of the environment to compare without revealing values")
+                    .arg(arg!(<ENV> "The environment to target"))
+                    .arg(
+                        arg!(--compare <FILE> "Compare with the fingerprint output of someone else")
+                            .required(false),
+                    ),
+            )
+            .subcommand(
+                App::new("fmt")
+                    .about("Format the template, local and environment files in the canonical style")
+                    .arg(arg!([ENV]... "The environments to target, default to all files"))
+                    .arg(arg!(--reorder "Reorder the variables to follow the template"))
+                    .arg(arg!(--check "Only check whether the files are formatted")),
+            )
+            .subcommand(
+                App::new("show")
+                    .about("Print the variables of the environment with the ${VAR} references resolved")
+                    .arg(arg!(<ENV> "The environment to target"))
+                    .arg(arg!(--raw "Print the values as they are written"))
+                    .arg(arg!(--"process-env" "Resolve the missing variables from the process environment")),
+            )
+            .subcommand(
+                App::new("exec")
+                    .about("Run the command with the variables of the environment, the plaintext is never written")
+                    .arg(arg!(<ENV> "The environment to target"))
+                    .arg(arg!(-f --force "Run the command even if the environment fails the verification"))
+                    .arg(arg!(<COMMAND>... "The command and its arguments, after --").last(true)),
+            )
+            .subcommand(
+                App::new("explain")
+                    .about("Show the files defining the variable, from the parents to the environment")
+                    .arg(arg!(<ENV> "The environment to target"))
+                    .arg(arg!(<KEY> "The variable to explain")),
+            )
+            .subcommand(
+                App::new("sign")
+                    .about("Sign the environment files to detect the changes made outside envm")
+                    .arg(arg!([ENV]... "The environments to target, default to all environments")),
+            )
+            .subcommand(
+                App::new("template")
+                    .about("Manage the template environment")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        App::new("generate")
+                            .about("Generate the template from the variables of all environments")
+                            .arg(arg!(-u --update "Add the missing variables to existing template"))
+                            .arg(
+                                arg!(--placeholder <VALUE> "The value to replace the values of environments")
+                                    .required(false)
+                                    .default_value(""),
+                            ),
+                    ),
+            )
+            .subcommand(
+                App::new("schema")
+                    .about("Manage the schema of the variables")
+                    .setting(AppSettings::SubcommandRequiredElseHelp)
+                    .subcommand(
+                        App::new("export")
+                            .about("Print the JSON Schema generated from the template"),
+                    ),
+            );
 
         Command { app }
     }
 
-    pub fn run(self) -> UseCase {
+    pub fn run(self) -> (UseCase, Options) {
         let matches = self.app.get_matches();
+        // The global options are read before dispatching the subcommand.
+        let options = Options {
+            reveal: is_present(&matches, "reveal"),
+            command: matches.subcommand_name().unwrap_or_default().to_string(),
+        };
         match matches.subcommand() {
             Some(("diff", sub_matches)) => {
                 let env = sub_matches.value_of("ENV").expect("required");
-                return UseCase::DiffEnvironment(String::from(env));
+                (UseCase::DiffEnvironment(String::from(env)), options)
+            }
+            Some(("verify", sub_matches)) => {
+                let envs = sub_matches
+                    .values_of("ENV")
+                    .map(|it| it.map(String::from).collect())
+                    .unwrap_or_default();
+                let fail_on_placeholder = sub_matches.is_present("fail-on-placeholder");
+                (
+                    UseCase::VerifyEnvironments(envs, fail_on_placeholder),
+                    options,
+                )
             }
             Some(("use", sub_matches)) => {
+                let force = sub_matches.is_present("force");
+                if let Some(stage) = sub_matches.value_of("stage") {
+                    return (UseCase::UseStage(String::from(stage), force), options);
+                }
                 let env = sub_matches.value_of("ENV").expect("required");
-                return UseCase::UseEnvironment(String::from(env));
-            }
-            Some(("init", _)) => {
-                return UseCase::InitConfiguration;
+                (UseCase::UseEnvironment(String::from(env), force), options)
             }
+            Some(("init", _)) => (UseCase::InitConfiguration, options),
             Some(("new", sub_matches)) => {
                 let env = sub_matches.value_of("ENV").expect("required");
-                return UseCase::NewEnvironment(String::from(env));
-            }
-            Some(("ls", _)) => {
-                return UseCase::ListEnvironments;
+                (UseCase::NewEnvironment(String::from(env)), options)
             }
+            Some(("ls", sub_matches)) => (
+                UseCase::ListEnvironments(sub_matches.is_present("matrix")),
+                options,
+            ),
             Some(("rm", sub_matches)) => {
                 let env = sub_matches.value_of("ENV").expect("required");
-                return UseCase::RemoveEnvironment(String::from(env));
+                (UseCase::RemoveEnvironment(String::from(env)), options)
+            }
+            Some(("now", _)) => (UseCase::ShowCurrentUsingEnvironment, options),
+            Some(("status", _)) => (UseCase::ShowStatus, options),
+            Some(("gitignore", _)) => (UseCase::Gitignore, options),
+            Some(("grep", sub_matches)) => {
+                let pattern = sub_matches.value_of("PATTERN").expect("required");
+                let scope = if sub_matches.is_present("keys") {
+                    SearchScope::Keys
+                } else if sub_matches.is_present("values") {
+                    SearchScope::Values
+                } else {
+                    SearchScope::All
+                };
+                let show_values = sub_matches.is_present("show-values");
+                (
+                    UseCase::SearchEnvironments(String::from(pattern), scope, show_values),
+                    options,
+                )
+            }
+            Some(("sync", sub_matches)) => {
+                let envs = sub_matches
+                    .values_of("ENV")
+                    .map(|it| it.map(String::from).collect())
+                    .unwrap_or_default();
+                let dry_run = sub_matches.is_present("dry-run");
+                (UseCase::SyncEnvironments(envs, dry_run), options)
+            }
+            Some(("prune", sub_matches)) => {
+                let envs = sub_matches
+                    .values_of("ENV")
+                    .map(|it| it.map(String::from).collect())
+                    .unwrap_or_default();
+                let dry_run = sub_matches.is_present("dry-run");
+                (UseCase::PruneEnvironments(envs, dry_run), options)
+            }
+            Some(("lint", sub_matches)) => {
+                let fix = sub_matches.is_present("fix");
+                (UseCase::LintEnvironments(fix), options)
+            }
+            Some(("scan", _)) => (UseCase::ScanSecrets, options),
+            Some(("encrypt", sub_matches)) => {
+                let env = sub_matches.value_of("ENV").expect("required");
+                let values = sub_matches.is_present("values");
+                (
+                    UseCase::EncryptEnvironment(String::from(env), values),
+                    options,
+                )
+            }
+            Some(("decrypt", sub_matches)) => {
+                let env = sub_matches.value_of("ENV").expect("required");
+                (UseCase::DecryptEnvironment(String::from(env)), options)
+            }
+            Some(("recipients", sub_matches)) => match sub_matches.subcommand() {
+                Some(("ls", _)) => (UseCase::ListRecipients, options),
+                Some(("add", sub_matches)) => {
+                    let key = sub_matches.value_of("KEY").expect("required");
+                    (UseCase::AddRecipient(String::from(key)), options)
+                }
+                Some(("rm", sub_matches)) => {
+                    let key = sub_matches.value_of("KEY").expect("required");
+                    (UseCase::RemoveRecipient(String::from(key)), options)
+                }
+                _ => unreachable!(),
+            },
+            Some(("agent", sub_matches)) => match sub_matches.subcommand() {
+                Some(("start", sub_matches)) => {
+                    let lifetime = sub_matches
+                        .value_of("lifetime")
+                        .and_then(|it| it.parse().ok())
+                        .expect("validated");
+                    let foreground = sub_matches.is_present("foreground");
+                    let socket = sub_matches.value_of("socket").map(String::from);
+                    (UseCase::StartAgent(lifetime, foreground, socket), options)
+                }
+                Some(("lock", _)) => (UseCase::LockAgent, options),
+                Some(("stop", _)) => (UseCase::StopAgent, options),
+                _ => unreachable!(),
+            },
+            Some(("fingerprint", sub_matches)) => {
+                let env = sub_matches.value_of("ENV").expect("required");
+                let compare = sub_matches.value_of("compare").map(String::from);
+                (UseCase::Fingerprint(String::from(env), compare), options)
             }
-            Some(("now", _)) => {
-                return UseCase::ShowCurrentUsingEnvironment;
+            Some(("fmt", sub_matches)) => {
+                let envs = sub_matches
+                    .values_of("ENV")
+                    .map(|it| it.map(String::from).collect())
+                    .unwrap_or_default();
+                let reorder = sub_matches.is_present("reorder");
+                let check = sub_matches.is_present("check");
+                (UseCase::FormatEnvironments(envs, reorder, check), options)
             }
-            Some(("gitignore", _)) => {
-                return UseCase::Gitignore;
+            Some(("show", sub_matches)) => {
+                let env = sub_matches.value_of("ENV").expect("required");
+                let raw = sub_matches.is_present("raw");
+                let process_env = sub_matches.is_present("process-env");
+                (
+                    UseCase::ShowEnvironment(String::from(env), raw, process_env),
+                    options,
+                )
+            }
+            Some(("exec", sub_matches)) => {
+                let env = sub_matches.value_of("ENV").expect("required");
+                let command = sub_matches
+                    .values_of("COMMAND")
+                    .expect("required")
+                    .map(String::from)
+                    .collect();
+                let force = sub_matches.is_present("force");
+                (
+                    UseCase::ExecEnvironment(String::from(env), command, force),
+                    options,
+                )
+            }
+            Some(("explain", sub_matches)) => {
+                let env = sub_matches.value_of("ENV").expect("required");
+                let key = sub_matches.value_of("KEY").expect("required");
+                (
+                    UseCase::ExplainVariable(String::from(env), String::from(key)),
+                    options,
+                )
             }
+            Some(("sign", sub_matches)) => {
+                let envs = sub_matches
+                    .values_of("ENV")
+                    .map(|it| it.map(String::from).collect())
+                    .unwrap_or_default();
+                (UseCase::SignEnvironments(envs), options)
+            }
+            Some(("template", sub_matches)) => match sub_matches.subcommand() {
+                Some(("generate", sub_matches)) => {
+                    let update = sub_matches.is_present("update");
+                    let placeholder = sub_matches.value_of("placeholder").expect("default");
+                    (
+                        UseCase::GenerateTemplate(update, String::from(placeholder)),
+                        options,
+                    )
+                }
+                _ => unreachable!(),
+            },
+            Some(("schema", sub_matches)) => match sub_matches.subcommand() {
+                Some(("export", _)) => (UseCase::ExportSchema, options),
+                _ => unreachable!(),
+            },
             _ => unreachable!(),
-        };
+        }
     }
 }
+
+// The global argument may be given after any subcommand.
+fn is_present(matches: &ArgMatches, name: &str) -> bool {
+    matches.is_present(name)
+        || matches
+            .subcommand()
+            .map(|(_, sub_matches)| is_present(sub_matches, name))
+            .unwrap_or(false)
+}
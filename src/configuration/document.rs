@@ -0,0 +1,217 @@
+use crate::configuration::parser::dotenv;
+
+// A dotenv file kept line by line, so we can edit the variables without touching the formatting
+// of the rest of the file.
+#[derive(Debug, Clone)]
+pub struct Document {
+    lines: Vec<String>,
+    line_ending: &'static str,
+    trailing_newline: bool,
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Document {
+        Document {
+            lines: content.lines().map(String::from).collect(),
+            line_ending: if content.contains("\r\n") {
+                "\r\n"
+            } else {
+                "\n"
+            },
+            trailing_newline: content.is_empty() || content.ends_with('\n'),
+        }
+    }
+
+    pub fn lines(&self) -> &Vec<String> {
+        &self.lines
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.lines
+            .iter()
+            .filter_map(|it| dotenv::parse_line(it))
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    // The index of the line which defines the key, the last one wins if the key is duplicated.
+    pub fn position(&self, key: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .rposition(|it| matches!(dotenv::parse_line(it), Some((k, _)) if k == key))
+    }
+
+    // The indexes of every line which defines the key, in the file order.
+    pub fn positions(&self, key: &str) -> Vec<usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, it)| matches!(dotenv::parse_line(it), Some((k, _)) if k == key))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    // The comment lines right above the key, which usually describe the variable.
+    pub fn comment_block(&self, key: &str) -> Vec<String> {
+        match self.position(key) {
+            Some(index) => self.comment_block_at(index),
+            None => vec![],
+        }
+    }
+
+    // The comment lines right above the line at the index.
+    pub fn comment_block_at(&self, index: usize) -> Vec<String> {
+        let start = self.comment_block_start(index);
+        self.lines[start..index].to_vec()
+    }
+
+    fn comment_block_start(&self, index: usize) -> usize {
+        let mut start = index;
+        while start > 0 && self.lines[start - 1].trim().starts_with('#') {
+            start -= 1;
+        }
+        start
+    }
+
+    // Replace the value of the key, return false if the key doesn't exist.
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        match self.position(key) {
+            Some(index) => {
+                self.lines[index] = format!("{}={}", key, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert_after(&mut self, key: &str, lines: Vec<String>) -> bool {
+        match self.position(key) {
+            Some(index) => {
+                self.lines.splice(index + 1..index + 1, lines);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Insert before the comment block of the key, so the comment still describe the key.
+    pub fn insert_before(&mut self, key: &str, lines: Vec<String>) -> bool {
+        match self.position(key) {
+            Some(index) => {
+                let start = self.comment_block_start(index);
+                self.lines.splice(start..start, lines);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn append(&mut self, lines: Vec<String>) {
+        if matches!(self.lines.last(), Some(last) if !last.trim().is_empty()) {
+            self.lines.push(String::new());
+        }
+        self.lines.extend(lines);
+        self.trailing_newline = true;
+    }
+
+    // The comment block above the key which opens a group of keys, like `# --- Database ---`,
+    // describes the whole group rather than the first key, so it's not attached to the key.
+    fn attached_comment_start(&self, index: usize) -> usize {
+        let start = self.comment_block_start(index);
+        let after_blank = start == 0 || self.lines[start - 1].trim().is_empty();
+        let followed_by_key = matches!(
+            self.lines.get(index + 1),
+            Some(next) if dotenv::parse_line(next).is_some()
+        );
+        if start < index && after_blank && followed_by_key {
+            index
+        } else {
+            start
+        }
+    }
+
+    // Remove every line defines the key, with the comment block attached to it.
+    pub fn remove(&mut self, key: &str) -> bool {
+        let mut removed = false;
+        while let Some(index) = self.position(key) {
+            let start = self.attached_comment_start(index);
+            self.lines.drain(start..=index);
+            removed = true;
+        }
+        removed
+    }
+}
+
+impl std::fmt::Display for Document {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.lines.join(self.line_ending))?;
+        if self.trailing_newline && !self.lines.is_empty() {
+            write!(f, "{}", self.line_ending)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_keep_formatting() {
+        let content = "# database\r\nDB_HOST = localhost\r\n\r\nDEBUG=true";
+        let document = Document::parse(content);
+        assert_eq!(document.keys(), vec!["DB_HOST", "DEBUG"]);
+        assert_eq!(document.to_string(), content);
+    }
+
+    #[test]
+    fn should_get_comment_block() {
+        let document = Document::parse("A=1\n# the host\n# of database\nDB_HOST=\n");
+        assert_eq!(
+            document.comment_block("DB_HOST"),
+            vec!["# the host", "# of database"]
+        );
+        assert!(document.comment_block("A").is_empty());
+    }
+
+    #[test]
+    fn should_insert_next_to_key() {
+        let mut document = Document::parse("A=1\n# b\nB=2\n");
+        document.insert_after("A", vec![String::from("C=3")]);
+        document.insert_before("B", vec![String::from("D=4")]);
+        assert_eq!(document.to_string(), "A=1\nC=3\nD=4\n# b\nB=2\n");
+    }
+
+    #[test]
+    fn should_set_value() {
+        let mut document = Document::parse("# a\nA = 1\n");
+        assert!(document.set("A", "2"));
+        assert!(!document.set("B", "2"));
+        assert_eq!(document.to_string(), "# a\nA=2\n");
+    }
+
+    #[test]
+    fn should_remove_key_with_comment() {
+        let mut document = Document::parse("A=1\n# b\nB=2\nC=3\n");
+        assert!(document.remove("B"));
+        assert!(!document.remove("B"));
+        assert_eq!(document.to_string(), "A=1\nC=3\n");
+    }
+
+    #[test]
+    fn should_keep_section_comment_on_remove() {
+        let content = "# --- Database ---\nDB_HOST=\nDB_PORT=\n\n# --- Cache ---\nREDIS_URL=\n";
+        let mut document = Document::parse(content);
+        assert!(document.remove("DB_HOST"));
+        assert_eq!(
+            document.to_string(),
+            "# --- Database ---\nDB_PORT=\n\n# --- Cache ---\nREDIS_URL=\n"
+        );
+        assert!(document.remove("REDIS_URL"));
+        assert_eq!(document.to_string(), "# --- Database ---\nDB_PORT=\n\n");
+    }
+}
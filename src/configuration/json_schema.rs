@@ -0,0 +1,281 @@
+use serde_json::{json, Map, Value};
+
+use crate::configuration::schema::{Field, Schema};
+
+// Read the schema from a JSON Schema, only the subset which makes sense for environment variables
+// is supported, the envm specific settings are stored in the `x-envm-*` keywords:
+//
+//   {
+//     "type": "object",
+//     "properties": {
+//       "PORT": { "type": "integer", "minimum": 1, "x-envm-type": "port" },
+//       "API_URL": { "type": "string", "format": "uri", "x-envm-schemes": ["https"] },
+//       "API_TOKEN": { "type": "string", "x-envm-secret": true }
+//     },
+//     "required": ["API_URL"],
+//     "x-envm-environments": { "production": { "required": ["API_TOKEN"] } }
+//   }
+pub fn parse(content: &str) -> Result<Schema, String> {
+    let root: Value = serde_json::from_str(content).map_err(|err| err.to_string())?;
+    let mut schema = Schema::default();
+    if let Some(properties) = root.get("properties") {
+        let properties = properties
+            .as_object()
+            .ok_or_else(|| String::from("`properties` should be an object"))?;
+        for (key, property) in properties {
+            schema.insert(key, parse_property(key, property)?);
+        }
+    }
+    for key in string_list(&root, "required")?.unwrap_or_default() {
+        schema.insert(
+            &key,
+            Field {
+                required: true,
+                ..Field::default()
+            },
+        );
+    }
+    if let Some(environments) = root.get("x-envm-environments") {
+        let environments = environments
+            .as_object()
+            .ok_or_else(|| String::from("`x-envm-environments` should be an object"))?;
+        for (env, settings) in environments {
+            for key in string_list(settings, "required")?.unwrap_or_default() {
+                schema.require_in(env, &key);
+            }
+        }
+    }
+    Ok(schema)
+}
+
+fn parse_property(key: &str, property: &Value) -> Result<Field, String> {
+    let kind = match (
+        property.get("x-envm-type").and_then(Value::as_str),
+        property.get("type").and_then(Value::as_str),
+        property.get("format").and_then(Value::as_str),
+    ) {
+        (Some(kind), _, _) => Some(kind.to_string()),
+        _ if property.get("enum").is_some() => Some(String::from("enum")),
+        (None, Some("integer"), _) => Some(String::from("int")),
+        (None, Some("number"), _) => Some(String::from("number")),
+        (None, Some("boolean"), _) => Some(String::from("bool")),
+        // The value of an environment variable is always a string, so the other types can't be
+        // checked and are rejected rather than silently accepted.
+        (None, Some(kind), _) if kind != "string" => {
+            return Err(format!("{}: unsupported type `{}`", key, kind))
+        }
+        (None, _, Some("uri")) => Some(String::from("url")),
+        (None, _, Some("email")) => Some(String::from("email")),
+        (None, _, Some("regex")) => Some(String::from("regex")),
+        _ => None,
+    };
+    // The pattern in JSON Schema is not anchored, but the pattern of envm match the whole value.
+    let pattern = property
+        .get("pattern")
+        .and_then(Value::as_str)
+        .map(|it| format!(".*(?:{}).*", it));
+    Ok(Field {
+        required: false,
+        kind,
+        secret: property.get("x-envm-secret").and_then(Value::as_bool) == Some(true),
+        default: property.get("default").map(to_string),
+        description: property
+            .get("description")
+            .and_then(Value::as_str)
+            .map(String::from),
+        min: property.get("minimum").map(to_string),
+        max: property.get("maximum").map(to_string),
+        values: property
+            .get("enum")
+            .and_then(Value::as_array)
+            .map(|it| it.iter().map(to_string).collect()),
+        pattern,
+        schemes: string_list(property, "x-envm-schemes")
+            .map_err(|err| format!("{}: {}", key, err))?,
+    })
+}
+
+// Generate the JSON Schema of the variables, the variables without field are treated as string.
+pub fn export(schema: &Schema, keys: &[String]) -> Value {
+    let mut properties = Map::new();
+    for key in keys {
+        let field = schema.get(key).cloned().unwrap_or_default();
+        properties.insert(key.clone(), export_property(&field));
+    }
+    let mut required: Vec<&String> = keys.iter().filter(|it| schema.is_required(it)).collect();
+    required.sort();
+    let mut root = json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    });
+    if !schema.environments().is_empty() {
+        let environments: Map<String, Value> = schema
+            .environments()
+            .iter()
+            .map(|(env, keys)| (env.clone(), json!({ "required": keys })))
+            .collect();
+        root["x-envm-environments"] = Value::Object(environments);
+    }
+    root
+}
+
+fn export_property(field: &Field) -> Value {
+    let mut property = Map::new();
+    let (kind, format) = match field.kind.as_deref() {
+        Some("int") | Some("port") => ("integer", None),
+        Some("number") => ("number", None),
+        Some("bool") => ("boolean", None),
+        Some("url") => ("string", Some("uri")),
+        Some("email") => ("string", Some("email")),
+        Some("regex") => ("string", Some("regex")),
+        _ => ("string", None),
+    };
+    property.insert(String::from("type"), json!(kind));
+    if let Some(format) = format {
+        property.insert(String::from("format"), json!(format));
+    }
+    if let Some(kind) = &field.kind {
+        property.insert(String::from("x-envm-type"), json!(kind));
+    }
+    if let Some(description) = &field.description {
+        property.insert(String::from("description"), json!(description));
+    }
+    if let Some(default) = &field.default {
+        property.insert(String::from("default"), json!(default));
+    }
+    if let Some(values) = &field.values {
+        property.insert(String::from("enum"), json!(values));
+    }
+    let number = |it: &String| match (it.parse::<i64>(), it.parse::<f64>()) {
+        (Ok(int), _) => Value::from(int),
+        (_, Ok(float)) if float.is_finite() => Value::from(float),
+        _ => json!(it),
+    };
+    if let Some(min) = &field.min {
+        property.insert(String::from("minimum"), number(min));
+    }
+    if let Some(max) = &field.max {
+        property.insert(String::from("maximum"), number(max));
+    }
+    if let Some(pattern) = &field.pattern {
+        property.insert(String::from("pattern"), json!(format!("^(?:{})$", pattern)));
+    }
+    if let Some(schemes) = &field.schemes {
+        property.insert(String::from("x-envm-schemes"), json!(schemes));
+    }
+    if field.secret {
+        property.insert(String::from("x-envm-secret"), json!(true));
+    }
+    Value::Object(property)
+}
+
+fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(it) => it.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn string_list(value: &Value, name: &str) -> Result<Option<Vec<String>>, String> {
+    match value.get(name) {
+        None => Ok(None),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|it| {
+                it.as_str()
+                    .map(String::from)
+                    .ok_or_else(|| format!("`{}` should be a list of string", name))
+            })
+            .collect::<Result<Vec<String>, String>>()
+            .map(Some),
+        Some(_) => Err(format!("`{}` should be a list of string", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::validation::validate_value;
+
+    #[test]
+    fn should_parse_json_schema() -> Result<(), String> {
+        let schema = parse(
+            r#"{
+                "type": "object",
+                "properties": {
+                    "PORT": { "type": "integer", "minimum": 1, "maximum": 65535, "default": 3000 },
+                    "API_URL": { "type": "string", "format": "uri", "x-envm-schemes": ["https"] },
+                    "LOG_LEVEL": { "enum": ["debug", "info"] },
+                    "NAME": { "type": "string", "pattern": "^[a-z]+" },
+                    "RATIO": { "type": "number", "maximum": 1 },
+                    "DEBUG": { "type": "boolean" },
+                    "API_TOKEN": { "type": "string", "x-envm-secret": true }
+                },
+                "required": ["API_URL"],
+                "x-envm-environments": { "production": { "required": ["API_TOKEN"] } }
+            }"#,
+        )?;
+
+        let port = schema.get("PORT").unwrap();
+        assert_eq!(port.kind, Some(String::from("int")));
+        assert_eq!(port.max, Some(String::from("65535")));
+        assert_eq!(port.default, Some(String::from("3000")));
+        assert!(schema.is_required("API_URL"));
+        assert!(validate_value(schema.get("API_URL").unwrap(), "http://example.com").is_err());
+        assert_eq!(
+            schema.get("LOG_LEVEL").unwrap().kind,
+            Some(String::from("enum"))
+        );
+        assert!(validate_value(schema.get("NAME").unwrap(), "abc1").is_ok());
+        assert!(validate_value(schema.get("NAME").unwrap(), "1abc").is_err());
+        assert!(validate_value(schema.get("RATIO").unwrap(), "0.5").is_ok());
+        assert!(validate_value(schema.get("RATIO").unwrap(), "1.5").is_err());
+        assert!(validate_value(schema.get("RATIO").unwrap(), "half").is_err());
+        assert!(validate_value(schema.get("DEBUG").unwrap(), "yes").is_ok());
+        assert!(validate_value(schema.get("DEBUG").unwrap(), "maybe").is_err());
+        assert!(schema.is_secret("API_TOKEN"));
+        assert!(schema.is_required_in("API_TOKEN", "production"));
+        assert!(!schema.is_required_in("API_TOKEN", "dev"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_parse_invalid_json_schema() {
+        assert!(parse("{").is_err());
+        assert!(parse(r#"{ "required": "API_URL" }"#).is_err());
+        let err = parse(r#"{ "properties": { "HOSTS": { "type": "array" } } }"#).unwrap_err();
+        assert!(err.contains("HOSTS: unsupported type `array`"));
+    }
+
+    #[test]
+    fn should_export_json_schema() -> Result<(), String> {
+        let mut schema = Schema::default();
+        schema.insert(
+            "PORT",
+            Field {
+                required: true,
+                kind: Some(String::from("port")),
+                max: Some(String::from("65535")),
+                ..Field::default()
+            },
+        );
+        let keys = vec![String::from("PORT"), String::from("DEBUG")];
+
+        let exported = export(&schema, &keys);
+
+        assert_eq!(exported["properties"]["PORT"]["type"], json!("integer"));
+        assert_eq!(exported["properties"]["PORT"]["maximum"], json!(65535));
+        assert_eq!(exported["properties"]["DEBUG"]["type"], json!("string"));
+        assert_eq!(exported["required"], json!(["PORT"]));
+
+        let imported = parse(&exported.to_string())?;
+        assert_eq!(
+            imported.get("PORT").unwrap().kind,
+            Some(String::from("port"))
+        );
+        assert!(imported.is_required("PORT"));
+        Ok(())
+    }
+}
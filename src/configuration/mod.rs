@@ -1,8 +1,15 @@
+use regex::Regex;
 use std::collections::HashMap;
 use std::{fs, path::Path};
 
+pub mod document;
+pub mod interpolation;
+pub mod json_schema;
 pub mod parser;
+pub mod schema;
+pub mod validation;
 
+use crate::configuration::document::Document;
 use crate::configuration::parser::dotenv;
 
 pub type Configuration = HashMap<String, String>;
@@ -12,6 +19,10 @@ pub fn parse(path: &Path) -> Configuration {
     dotenv::parse(&content)
 }
 
+pub fn parse_document(document: &Document) -> Configuration {
+    dotenv::parse(&document.to_string())
+}
+
 // This function return (missing, extra),
 // the missing mean appear in left, but not appear in right
 // extra mean appear in right, but not appear in left
@@ -22,12 +33,12 @@ pub fn compare<'a>(
     let missing: Vec<String> = config_left
         .keys()
         .filter(|it| !config_right.contains_key(it as &str))
-        .map(|it| it.clone())
+        .cloned()
         .collect();
     let extra: Vec<String> = config_right
         .keys()
         .filter(|it| !config_left.contains_key(it as &str))
-        .map(|it| it.clone())
+        .cloned()
         .collect();
     (
         match missing.len() {
@@ -40,3 +51,136 @@ pub fn compare<'a>(
         },
     )
 }
+
+// Insert the variables which appear in template but not in target, with the comment block above
+// them, next to the variables around them in template, return the inserted lines.
+pub fn sync(template: &Document, target: &mut Document) -> Vec<String> {
+    let keys = template.keys();
+    let mut inserted = vec![];
+    for (index, key) in keys.iter().enumerate() {
+        if target.contains_key(key) {
+            continue;
+        }
+        let line = template.lines()[template.position(key).unwrap()].clone();
+        let mut lines = template.comment_block(key);
+        lines.push(line.clone());
+
+        let previous = keys[..index]
+            .iter()
+            .rev()
+            .find(|it| target.contains_key(it));
+        let next = keys[index + 1..].iter().find(|it| target.contains_key(it));
+        match (previous, next) {
+            (Some(previous), _) => {
+                target.insert_after(previous, lines);
+            }
+            (None, Some(next)) => {
+                target.insert_before(next, lines);
+            }
+            (None, None) => target.append(lines),
+        }
+        inserted.push(line);
+    }
+    inserted
+}
+
+// Find the variables still holding a placeholder, which means the value is the same as the
+// non-empty value in template, or the value matches one of the placeholder patterns.
+pub fn find_placeholders(
+    template: &Configuration,
+    target: &Configuration,
+    patterns: &[Regex],
+) -> Vec<String> {
+    let mut keys: Vec<String> = target
+        .iter()
+        .filter(|(key, value)| {
+            let value = dotenv::unquote(value);
+            let same_as_template = matches!(
+                template.get(key.as_str()).map(|it| dotenv::unquote(it)),
+                Some(it) if !it.is_empty() && it == value
+            );
+            !value.is_empty() && (same_as_template || patterns.iter().any(|it| it.is_match(&value)))
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+    keys.sort();
+    keys
+}
+
+// Build a template from the union of the variables in the sources, the values are replaced by
+// the placeholder, and the comment block is kept only when it is the same in every source.
+pub fn generate_template(sources: &[Document], placeholder: &str) -> Document {
+    let mut template = Document::parse("");
+    for source in sources {
+        let mut lines = vec![];
+        for key in source.keys() {
+            let comment_block = source.comment_block(&key);
+            let is_common = sources
+                .iter()
+                .filter(|it| it.contains_key(&key))
+                .all(|it| it.comment_block(&key) == comment_block);
+            if is_common {
+                lines.extend(comment_block);
+            }
+            lines.push(format!("{}={}", key, placeholder));
+        }
+        sync(&Document::parse(&lines.join("\n")), &mut template);
+    }
+    template
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_sync_missing_variables_next_to_neighbors() {
+        let template =
+            Document::parse("# app\nAPP_URL=\nAPP_PORT=3000\n\n# db\nDB_HOST=localhost\n");
+        let mut target = Document::parse("APP_URL=https://example.com\n\nDB_HOST = db\n");
+
+        let inserted = sync(&template, &mut target);
+
+        assert_eq!(inserted, vec!["APP_PORT=3000"]);
+        assert_eq!(
+            target.to_string(),
+            "APP_URL=https://example.com\nAPP_PORT=3000\n\nDB_HOST = db\n"
+        );
+    }
+
+    #[test]
+    fn should_sync_before_next_variable_with_comment() {
+        let template = Document::parse("# app\nAPP_URL=\nDB_HOST=\n");
+        let mut target = Document::parse("DB_HOST=db");
+
+        sync(&template, &mut target);
+
+        assert_eq!(target.to_string(), "# app\nAPP_URL=\nDB_HOST=db");
+    }
+
+    #[test]
+    fn should_find_placeholders() {
+        let template = dotenv::parse("API_KEY=your-api-key-here\nPORT=\nHOST=\nNAME=\n");
+        let target =
+            dotenv::parse("API_KEY=\"your-api-key-here\"\nPORT=80\nHOST=changeme\nNAME=\n");
+        let patterns = vec![Regex::new("^changeme$").unwrap()];
+
+        assert_eq!(
+            find_placeholders(&template, &target, &patterns),
+            vec!["API_KEY", "HOST"]
+        );
+    }
+
+    #[test]
+    fn should_generate_template_from_union_of_sources() {
+        let dev = Document::parse("# app\nAPP_URL=http://localhost\n# local db\nDB_HOST=db\n");
+        let production = Document::parse("# prod app\nAPP_URL=https://example.com\nSECRET=xyz\n");
+
+        let template = generate_template(&[dev, production], "");
+
+        assert_eq!(
+            template.to_string(),
+            "APP_URL=\nSECRET=\n# local db\nDB_HOST=\n"
+        );
+    }
+}
@@ -1,24 +1,71 @@
 use std::collections::HashMap;
 
+// A key-value pair with the line number (start from 1) it appears in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
 // Note: the comment after the value will stay, however in this project we only care the key.
 // not support mulit lines
 pub fn parse(content: &str) -> HashMap<String, String> {
+    entries(content)
+        .into_iter()
+        .map(|it| (it.key, it.value))
+        .collect()
+}
+
+pub fn entries(content: &str) -> Vec<Entry> {
     content
         .lines()
-        .map(|it| it.trim())
-        .filter(|it| !(it.starts_with("#") || it.is_empty() || !it.contains('=')))
-        .map(|it| it.splitn(2, '=').collect())
-        .map(|it: Vec<&str>| match it.len() {
-            1 => (it[0].trim().to_string().clone(), String::new()),
-            2 => (
-                it[0].trim().to_string().clone(),
-                it[1].trim().to_string().clone(),
-            ),
-            _ => panic!("not a key-value pair: {:?}", it),
+        .enumerate()
+        .filter_map(|(index, it)| {
+            parse_line(it).map(|(key, value)| Entry {
+                line: index + 1,
+                key,
+                value,
+            })
         })
         .collect()
 }
 
+// Return the key and value if the line is a key-value pair, comments and blank lines are ignored.
+pub fn parse_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.starts_with('#') || line.is_empty() {
+        return None;
+    }
+    line.split_once('=')
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+}
+
+// Remove the quotes around the value, the escaped characters in double quotes are unescaped.
+pub fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return value[1..value.len() - 1].to_string();
+    }
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let mut unquoted = String::new();
+        let mut chars = value[1..value.len() - 1].chars();
+        while let Some(it) = chars.next() {
+            match (it, chars.clone().next()) {
+                ('\\', Some('n')) => unquoted.push('\n'),
+                ('\\', Some(escaped @ ('"' | '\\'))) => unquoted.push(escaped),
+                _ => {
+                    unquoted.push(it);
+                    continue;
+                }
+            }
+            chars.next();
+        }
+        return unquoted;
+    }
+    value.to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -39,4 +86,26 @@ mod tests {
             Some(&String::from("http://localhost:3000"))
         );
     }
+
+    #[test]
+    fn should_parse_entries_with_line_number() {
+        let entries = entries("# comment\nAPP_URL=http://localhost:3000\n\nDEBUG = true\n");
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].line, 2);
+        assert_eq!(entries[0].key, "APP_URL");
+        assert_eq!(entries[1].line, 4);
+        assert_eq!(entries[1].key, "DEBUG");
+        assert_eq!(entries[1].value, "true");
+    }
+
+    #[test]
+    fn should_unquote_value() {
+        assert_eq!(unquote("plain"), "plain");
+        assert_eq!(unquote("'single $HOME'"), "single $HOME");
+        assert_eq!(unquote(r#""say \"hi\"""#), "say \"hi\"");
+        assert_eq!(unquote(r#""a\\nb\nc""#), "a\\nb\nc");
+        assert_eq!(unquote(r#""""#), "");
+        assert_eq!(unquote("\""), "\"");
+    }
 }
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use crate::configuration::document::Document;
+
+// The description of a variable, written as annotations in the comment block above the variable
+// in template, for example:
+//
+//   # @required @type=url @description=The public URL of the app
+//   APP_URL=
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Field {
+    pub required: bool,
+    pub kind: Option<String>,
+    pub secret: bool,
+    pub default: Option<String>,
+    pub description: Option<String>,
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub values: Option<Vec<String>>,
+    pub pattern: Option<String>,
+    pub schemes: Option<Vec<String>>,
+}
+
+impl Field {
+    // Merge the other field into this one, the values of the other field take precedence.
+    pub fn merge(&mut self, other: Field) {
+        self.required |= other.required;
+        self.secret |= other.secret;
+        self.kind = other.kind.or(self.kind.take());
+        self.default = other.default.or(self.default.take());
+        self.description = other.description.or(self.description.take());
+        self.min = other.min.or(self.min.take());
+        self.max = other.max.or(self.max.take());
+        self.values = other.values.or(self.values.take());
+        self.pattern = other.pattern.or(self.pattern.take());
+        self.schemes = other.schemes.or(self.schemes.take());
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Schema {
+    fields: HashMap<String, Field>,
+    // The variables only required in the specific environment.
+    environments: HashMap<String, Vec<String>>,
+}
+
+impl Schema {
+    pub fn from_template(template: &Document) -> Schema {
+        let fields = template
+            .keys()
+            .into_iter()
+            .map(|key| {
+                let mut field = Field::default();
+                for (name, value) in template
+                    .comment_block(&key)
+                    .iter()
+                    .flat_map(|it| parse_annotations(it))
+                {
+                    match (name.as_str(), value) {
+                        ("required", _) => field.required = true,
+                        ("secret", _) => field.secret = true,
+                        ("type", Some(value)) => field.kind = Some(value),
+                        ("default", Some(value)) => field.default = Some(value),
+                        ("description", Some(value)) => field.description = Some(value),
+                        ("min", Some(value)) => field.min = Some(value),
+                        ("max", Some(value)) => field.max = Some(value),
+                        ("values", Some(value)) => field.values = Some(split_list(&value)),
+                        ("pattern", Some(value)) => field.pattern = Some(value),
+                        ("schemes", Some(value)) => field.schemes = Some(split_list(&value)),
+                        _ => (),
+                    }
+                }
+                (key, field)
+            })
+            .collect();
+        Schema {
+            fields,
+            environments: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, key: &str, field: Field) {
+        self.fields.entry(key.to_string()).or_default().merge(field);
+    }
+
+    pub fn require_in(&mut self, env: &str, key: &str) {
+        self.environments
+            .entry(env.to_string())
+            .or_default()
+            .push(key.to_string());
+    }
+
+    pub fn merge(&mut self, other: Schema) {
+        for (key, field) in other.fields {
+            self.insert(&key, field);
+        }
+        for (env, keys) in other.environments {
+            self.environments.entry(env).or_default().extend(keys);
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Field> {
+        self.fields.get(key)
+    }
+
+    pub fn is_required(&self, key: &str) -> bool {
+        matches!(self.get(key), Some(it) if it.required)
+    }
+
+    pub fn is_required_in(&self, key: &str, env: &str) -> bool {
+        self.is_required(key)
+            || matches!(self.environments.get(env), Some(keys) if keys.iter().any(|it| it == key))
+    }
+
+    pub fn required_in(&self, env: &str) -> Vec<String> {
+        let mut keys: Vec<String> = self
+            .fields
+            .iter()
+            .filter(|(_, it)| it.required)
+            .map(|(key, _)| key.clone())
+            .chain(self.environments.get(env).cloned().unwrap_or_default())
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys
+    }
+
+    pub fn environments(&self) -> &HashMap<String, Vec<String>> {
+        &self.environments
+    }
+
+    pub fn is_secret(&self, key: &str) -> bool {
+        matches!(self.get(key), Some(it) if it.secret)
+    }
+
+    pub fn fields(&self) -> &HashMap<String, Field> {
+        &self.fields
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split('|').map(|it| it.trim().to_string()).collect()
+}
+
+// Parse the annotations in a comment line, an annotation start with `@` after a whitespace, and
+// the value after `=` lasts until the next annotation, so the description can contain spaces.
+fn parse_annotations(line: &str) -> Vec<(String, Option<String>)> {
+    let line = line.trim();
+    if !line.starts_with('#') {
+        return vec![];
+    }
+    let line = line.trim_start_matches('#');
+    let mut starts: Vec<usize> = line
+        .char_indices()
+        .filter(|(index, it)| {
+            *it == '@' && (*index == 0 || line[..*index].ends_with(char::is_whitespace))
+        })
+        .map(|(index, _)| index)
+        .collect();
+    starts.push(line.len());
+    starts
+        .windows(2)
+        .map(|it| &line[it[0] + 1..it[1]])
+        .map(|it| match it.split_once('=') {
+            Some((name, value)) => (
+                name.trim().to_string(),
+                Some(value.trim().trim_matches('"').to_string()),
+            ),
+            None => (it.trim().to_string(), None),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_annotations() {
+        let annotations =
+            parse_annotations("# @required @type=url @description=The URL of admin@example.com");
+        assert_eq!(
+            annotations,
+            vec![
+                (String::from("required"), None),
+                (String::from("type"), Some(String::from("url"))),
+                (
+                    String::from("description"),
+                    Some(String::from("The URL of admin@example.com"))
+                ),
+            ]
+        );
+        assert!(parse_annotations("# just a comment").is_empty());
+    }
+
+    #[test]
+    fn should_build_schema_from_template() {
+        let template = Document::parse(
+            "# The public URL\n# @required @type=url\nAPP_URL=\n\n# @secret @default=\"abc\"\nTOKEN=\nDEBUG=false\n",
+        );
+        let schema = Schema::from_template(&template);
+
+        assert!(schema.is_required("APP_URL"));
+        assert_eq!(
+            schema.get("APP_URL").unwrap().kind,
+            Some(String::from("url"))
+        );
+        assert!(schema.is_secret("TOKEN"));
+        assert_eq!(
+            schema.get("TOKEN").unwrap().default,
+            Some(String::from("abc"))
+        );
+        assert_eq!(schema.get("DEBUG"), Some(&Field::default()));
+        let template = Document::parse("# @type=enum @values=debug | info\nLOG_LEVEL=info\n");
+        let schema = Schema::from_template(&template);
+        assert_eq!(
+            schema.get("LOG_LEVEL").unwrap().values,
+            Some(vec![String::from("debug"), String::from("info")])
+        );
+        assert!(!schema.is_required("MISSING"));
+    }
+
+    #[test]
+    fn should_merge_schema() {
+        let template = Document::parse("# @type=int @description=The port\nPORT=\n");
+        let mut schema = Schema::from_template(&template);
+        let mut other = Schema::default();
+        other.insert(
+            "PORT",
+            Field {
+                required: true,
+                kind: Some(String::from("port")),
+                ..Field::default()
+            },
+        );
+        other.require_in("production", "SENTRY_DSN");
+        schema.merge(other);
+
+        let port = schema.get("PORT").unwrap();
+        assert!(port.required);
+        assert_eq!(port.kind, Some(String::from("port")));
+        assert_eq!(port.description, Some(String::from("The port")));
+        assert!(schema.is_required_in("SENTRY_DSN", "production"));
+        assert!(!schema.is_required_in("SENTRY_DSN", "dev"));
+        assert_eq!(schema.required_in("production"), vec!["PORT", "SENTRY_DSN"]);
+    }
+}
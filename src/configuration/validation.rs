@@ -0,0 +1,263 @@
+use regex::Regex;
+
+use crate::configuration::parser::dotenv::{self, Entry};
+use crate::configuration::schema::{Field, Schema};
+
+// A value which doesn't satisfy the type or constraints of the variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+    pub message: String,
+}
+
+pub fn validate(schema: &Schema, entries: &[Entry]) -> Vec<Issue> {
+    entries
+        .iter()
+        .filter_map(|entry| {
+            let field = schema.get(&entry.key)?;
+            let value = dotenv::unquote(&entry.value);
+            // The empty value is checked by the required annotation.
+            if value.is_empty() {
+                return None;
+            }
+            validate_value(field, &value).err().map(|message| Issue {
+                line: entry.line,
+                key: entry.key.clone(),
+                value,
+                message,
+            })
+        })
+        .collect()
+}
+
+pub fn validate_value(field: &Field, value: &str) -> Result<(), String> {
+    match field.kind.as_deref() {
+        None | Some("string") => (),
+        Some("int") => {
+            let number = parse_int(value)?;
+            check_range(field, number, parse_int)?;
+        }
+        Some("number") => {
+            let number = parse_number(value)?;
+            check_range(field, number, parse_number)?;
+        }
+        Some("port") => {
+            let port = parse_int(value)?;
+            if !(1..=65535).contains(&port) {
+                return Err(format!("expected a port between 1 and 65535, got {}", port));
+            }
+            check_range(field, port, parse_int)?;
+        }
+        Some("bool") => {
+            let bools = ["true", "false", "1", "0", "yes", "no", "on", "off"];
+            if !bools.contains(&value.to_lowercase().as_str()) {
+                return Err(format!("expected one of {}", bools.join(", ")));
+            }
+        }
+        Some("url") => {
+            let re = Regex::new(r"^([a-zA-Z][a-zA-Z0-9+.-]*)://[^\s/?#]+\S*$").unwrap();
+            let scheme = match re.captures(value) {
+                Some(caps) => caps[1].to_lowercase(),
+                None => return Err(String::from("expected an url")),
+            };
+            if let Some(schemes) = &field.schemes {
+                if !schemes.iter().any(|it| it.to_lowercase() == scheme) {
+                    return Err(format!(
+                        "expected an url with scheme {}, got {}",
+                        schemes.join(", "),
+                        scheme
+                    ));
+                }
+            }
+        }
+        Some("email") => {
+            let re = Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$").unwrap();
+            if !re.is_match(value) {
+                return Err(String::from("expected an email"));
+            }
+        }
+        Some("enum") => {
+            let values = field.values.clone().unwrap_or_default();
+            if !values.iter().any(|it| it == value) {
+                return Err(format!("expected one of {}", values.join(", ")));
+            }
+        }
+        Some("regex") => {
+            Regex::new(value).map_err(|_| String::from("expected a regular expression"))?;
+        }
+        Some("duration") => {
+            let duration = parse_duration(value)?;
+            check_range(field, duration, parse_duration)?;
+        }
+        Some(kind) => return Err(format!("unknown type '{}' in template", kind)),
+    }
+    if let Some(pattern) = &field.pattern {
+        let re = Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|_| format!("invalid pattern '{}' in template", pattern))?;
+        if !re.is_match(value) {
+            return Err(format!("expected to match pattern {}", pattern));
+        }
+    }
+    Ok(())
+}
+
+fn parse_int(value: &str) -> Result<i64, String> {
+    value
+        .parse::<i64>()
+        .map_err(|_| String::from("expected an integer"))
+}
+
+fn parse_number(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|it| it.is_finite())
+        .ok_or_else(|| String::from("expected a number"))
+}
+
+// Parse the duration like `500ms`, `30s` or `1h30m` into milliseconds.
+fn parse_duration(value: &str) -> Result<i64, String> {
+    let err = || String::from("expected a duration, for example 500ms, 30s, 5m, 1h or 1d");
+    let re = Regex::new(r"^(?:\d+(?:ms|s|m|h|d))+$").unwrap();
+    if !re.is_match(value) {
+        return Err(err());
+    }
+    let part = Regex::new(r"(\d+)(ms|s|m|h|d)").unwrap();
+    // The huge durations are rejected rather than overflow.
+    let total = part.captures_iter(value).try_fold(0i64, |total, caps| {
+        let amount = caps[1].parse::<i64>().map_err(|_| err())?;
+        let unit = match &caps[2] {
+            "ms" => 1,
+            "s" => 1000,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            _ => 24 * 60 * 60 * 1000,
+        };
+        amount
+            .checked_mul(unit)
+            .and_then(|it| total.checked_add(it))
+            .ok_or_else(err)
+    });
+    total
+}
+
+fn check_range<T: PartialOrd>(
+    field: &Field,
+    value: T,
+    parse: fn(&str) -> Result<T, String>,
+) -> Result<(), String> {
+    if let Some(min) = &field.min {
+        if value < parse(min).map_err(|_| format!("invalid min '{}' in template", min))? {
+            return Err(format!("expected at least {}", min));
+        }
+    }
+    if let Some(max) = &field.max {
+        if value > parse(max).map_err(|_| format!("invalid max '{}' in template", max))? {
+            return Err(format!("expected at most {}", max));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(kind: &str) -> Field {
+        Field {
+            kind: Some(String::from(kind)),
+            ..Field::default()
+        }
+    }
+
+    #[test]
+    fn should_validate_int_range() {
+        let mut int = field("int");
+        int.min = Some(String::from("1"));
+        int.max = Some(String::from("10"));
+        assert!(validate_value(&int, "5").is_ok());
+        assert!(validate_value(&int, "abc").is_err());
+        assert!(validate_value(&int, "0").is_err());
+        assert!(validate_value(&int, "11").is_err());
+    }
+
+    #[test]
+    fn should_validate_number_range() {
+        let mut number = field("number");
+        number.min = Some(String::from("0"));
+        number.max = Some(String::from("1.5"));
+        assert!(validate_value(&number, "0.25").is_ok());
+        assert!(validate_value(&number, "1e-3").is_ok());
+        assert!(validate_value(&number, "2").is_err());
+        assert!(validate_value(&number, "NaN").is_err());
+        assert!(validate_value(&number, "abc").is_err());
+    }
+
+    #[test]
+    fn should_validate_port_and_bool() {
+        assert!(validate_value(&field("port"), "8080").is_ok());
+        assert!(validate_value(&field("port"), "70000").is_err());
+        assert!(validate_value(&field("bool"), "True").is_ok());
+        assert!(validate_value(&field("bool"), "maybe").is_err());
+    }
+
+    #[test]
+    fn should_validate_url_scheme() {
+        let mut url = field("url");
+        url.schemes = Some(vec![String::from("https")]);
+        assert!(validate_value(&url, "https://example.com/path").is_ok());
+        assert!(validate_value(&url, "http://example.com").is_err());
+        assert!(validate_value(&url, "example.com").is_err());
+    }
+
+    #[test]
+    fn should_validate_email_enum_and_regex() {
+        assert!(validate_value(&field("email"), "admin@example.com").is_ok());
+        assert!(validate_value(&field("email"), "admin").is_err());
+        let mut level = field("enum");
+        level.values = Some(vec![String::from("debug"), String::from("info")]);
+        assert!(validate_value(&level, "info").is_ok());
+        assert!(validate_value(&level, "trace").is_err());
+        assert!(validate_value(&field("regex"), "^a+$").is_ok());
+        assert!(validate_value(&field("regex"), "(").is_err());
+    }
+
+    #[test]
+    fn should_validate_duration() {
+        let mut timeout = field("duration");
+        timeout.max = Some(String::from("1m"));
+        assert!(validate_value(&timeout, "30s").is_ok());
+        assert!(validate_value(&timeout, "1m30s").is_err());
+        assert!(validate_value(&timeout, "30").is_err());
+        let err = validate_value(&field("duration"), "1000000000000d").unwrap_err();
+        assert!(err.starts_with("expected a duration"));
+        let err = validate_value(&field("duration"), "9223372036854775807ms1ms").unwrap_err();
+        assert!(err.starts_with("expected a duration"));
+    }
+
+    #[test]
+    fn should_validate_pattern() {
+        let field = Field {
+            pattern: Some(String::from("[a-z]+")),
+            ..Field::default()
+        };
+        assert!(validate_value(&field, "abc").is_ok());
+        assert!(validate_value(&field, "abc1").is_err());
+    }
+
+    #[test]
+    fn should_report_issue_with_line() {
+        let template = crate::configuration::document::Document::parse("# @type=port\nPORT=\n");
+        let schema = Schema::from_template(&template);
+        let entries = dotenv::entries("# comment\nPORT=\"abc\"\n");
+
+        let issues = validate(&schema, &entries);
+
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].line, 2);
+        assert_eq!(issues[0].key, "PORT");
+        assert_eq!(issues[0].value, "abc");
+    }
+}
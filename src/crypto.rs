@@ -0,0 +1,218 @@
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::secrecy::SecretString;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+// The passphrase is read from the environment variable first, so it can be used in CI.
+pub const PASSPHRASE_VARIABLE: &str = "ENVM_PASSPHRASE";
+pub const IDENTITY_FILE_VARIABLE: &str = "ENVM_IDENTITY_FILE";
+
+pub mod values;
+
+// The encrypted files are age files in ASCII armor, so they can be diffed and committed as text.
+// The smaller payloads which should fit in one line are the base64 of the binary age files.
+pub fn is_encrypted(content: &str) -> bool {
+    content
+        .trim_start()
+        .starts_with("-----BEGIN AGE ENCRYPTED FILE-----")
+}
+
+// Encrypt with a key derived from the passphrase by scrypt, the content is authenticated by
+// ChaCha20-Poly1305.
+pub fn encrypt(plaintext: &str, passphrase: &str, armor: bool) -> Result<String, String> {
+    let recipient = age::scrypt::Recipient::new(SecretString::from(passphrase.to_string()));
+    encrypt_with(plaintext, vec![&recipient as _], armor)
+}
+
+// Encrypt to the X25519 public keys, any of the private keys can decrypt it.
+pub fn encrypt_to_recipients(
+    plaintext: &str,
+    recipients: &[String],
+    armor: bool,
+) -> Result<String, String> {
+    let recipients = recipients
+        .iter()
+        .map(|it| parse_recipient(it))
+        .collect::<Result<Vec<age::x25519::Recipient>, String>>()?;
+    encrypt_with(
+        plaintext,
+        recipients.iter().map(|it| it as _).collect(),
+        armor,
+    )
+}
+
+fn encrypt_with(
+    plaintext: &str,
+    recipients: Vec<&dyn age::Recipient>,
+    armor: bool,
+) -> Result<String, String> {
+    let encryptor =
+        age::Encryptor::with_recipients(recipients.into_iter()).map_err(|err| err.to_string())?;
+    let mut encrypted = vec![];
+    let format = if armor {
+        Format::AsciiArmor
+    } else {
+        Format::Binary
+    };
+    let armored =
+        ArmoredWriter::wrap_output(&mut encrypted, format).map_err(|err| err.to_string())?;
+    let mut writer = encryptor
+        .wrap_output(armored)
+        .map_err(|err| err.to_string())?;
+    writer
+        .write_all(plaintext.as_bytes())
+        .map_err(|err| err.to_string())?;
+    writer
+        .finish()
+        .and_then(|it| it.finish())
+        .map_err(|err| err.to_string())?;
+    if armor {
+        Ok(String::from_utf8(encrypted).unwrap())
+    } else {
+        Ok(STANDARD.encode(encrypted))
+    }
+}
+
+fn to_bytes(encrypted: &str) -> Result<Vec<u8>, String> {
+    if is_encrypted(encrypted) {
+        Ok(encrypted.as_bytes().to_vec())
+    } else {
+        STANDARD
+            .decode(encrypted.trim())
+            .map_err(|err| err.to_string())
+    }
+}
+
+pub fn decrypt(encrypted: &str, passphrase: &str) -> Result<String, String> {
+    let identity = age::scrypt::Identity::new(SecretString::from(passphrase.to_string()));
+    decrypt_with(encrypted, vec![&identity as _])
+}
+
+// Decrypt with the private keys in the content of an age identity file.
+pub fn decrypt_with_identity(encrypted: &str, identity: &str) -> Result<String, String> {
+    let identities = age::IdentityFile::from_buffer(identity.as_bytes())
+        .and_then(|it| {
+            it.into_identities()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+        })
+        .map_err(|err| format!("invalid identity: {}", err))?;
+    decrypt_with(encrypted, identities.iter().map(|it| it.as_ref()).collect())
+}
+
+fn decrypt_with(encrypted: &str, identities: Vec<&dyn age::Identity>) -> Result<String, String> {
+    let encrypted = to_bytes(encrypted)?;
+    let decryptor = age::Decryptor::new_buffered(ArmoredReader::new(&encrypted[..]))
+        .map_err(|err| err.to_string())?;
+    let mut reader = decryptor
+        .decrypt(identities.into_iter())
+        .map_err(|err| err.to_string())?;
+    let mut plaintext = String::new();
+    reader
+        .read_to_string(&mut plaintext)
+        .map_err(|err| err.to_string())?;
+    Ok(plaintext)
+}
+
+// The file is encrypted with passphrase or to recipients, which is told by the header.
+pub fn is_passphrase_encrypted(encrypted: &str) -> bool {
+    match to_bytes(encrypted) {
+        Ok(encrypted) => age::Decryptor::new_buffered(ArmoredReader::new(&encrypted[..]))
+            .map(|it| it.is_scrypt())
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+pub fn parse_recipient(recipient: &str) -> Result<age::x25519::Recipient, String> {
+    recipient
+        .trim()
+        .parse::<age::x25519::Recipient>()
+        .map_err(|err| format!("invalid recipient '{}': {}", recipient, err))
+}
+
+// Read the private keys from `ENVM_IDENTITY_FILE`, default to `~/.config/envm/identity.txt`, the
+// file is in the format generated by `age-keygen`.
+pub fn read_identity() -> Result<String, String> {
+    let identity_path = match env::var(IDENTITY_FILE_VARIABLE) {
+        Ok(it) => PathBuf::from(it),
+        Err(_) => {
+            let home =
+                env::var("HOME").map_err(|_| String::from("cannot found the home directory"))?;
+            Path::new(&home).join(".config/envm/identity.txt")
+        }
+    };
+    fs::read_to_string(&identity_path)
+        .map_err(|err| format!("cannot read {}: {}", identity_path.display(), err))
+}
+
+// Read the passphrase from `ENVM_PASSPHRASE` or the terminal, ask twice when it's used to encrypt
+// a new file since a typo cannot be recovered.
+pub fn read_passphrase(confirm: bool) -> Result<String, String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_VARIABLE) {
+        return Ok(passphrase);
+    }
+    let passphrase =
+        rpassword::read_password_from_tty(Some("passphrase: ")).map_err(|err| err.to_string())?;
+    if passphrase.is_empty() {
+        return Err(String::from("the passphrase cannot be empty"));
+    }
+    if confirm {
+        let confirmed = rpassword::read_password_from_tty(Some("confirm passphrase: "))
+            .map_err(|err| err.to_string())?;
+        if confirmed != passphrase {
+            return Err(String::from("the passphrases don't match"));
+        }
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+
+    #[test]
+    fn should_encrypt_and_decrypt() -> Result<(), String> {
+        let encrypted = encrypt("TOKEN=s3cret\n", "correct horse", true)?;
+
+        assert!(is_encrypted(&encrypted));
+        assert!(!encrypted.contains("s3cret"));
+        assert_eq!(decrypt(&encrypted, "correct horse")?, "TOKEN=s3cret\n");
+        assert!(decrypt(&encrypted, "wrong horse").is_err());
+        assert!(!is_encrypted("TOKEN=s3cret\n"));
+        assert!(is_passphrase_encrypted(&encrypted));
+
+        let encrypted = encrypt("s3cret", "correct horse", false)?;
+        assert!(!encrypted.contains('\n'));
+        assert!(is_passphrase_encrypted(&encrypted));
+        assert_eq!(decrypt(&encrypted, "correct horse")?, "s3cret");
+        Ok(())
+    }
+
+    #[test]
+    fn should_encrypt_to_recipients() -> Result<(), String> {
+        let alice = age::x25519::Identity::generate();
+        let bob = age::x25519::Identity::generate();
+        let eve = age::x25519::Identity::generate();
+        let recipients = vec![alice.to_public().to_string(), bob.to_public().to_string()];
+
+        let encrypted = encrypt_to_recipients("TOKEN=s3cret\n", &recipients, true)?;
+
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_passphrase_encrypted(&encrypted));
+        for identity in [&alice, &bob] {
+            let identity = identity.to_string().expose_secret().to_string();
+            assert_eq!(
+                decrypt_with_identity(&encrypted, &identity)?,
+                "TOKEN=s3cret\n"
+            );
+        }
+        let eve = eve.to_string().expose_secret().to_string();
+        assert!(decrypt_with_identity(&encrypted, &eve).is_err());
+        assert!(encrypt_to_recipients("", &[String::from("age1invalid")], true).is_err());
+        Ok(())
+    }
+}
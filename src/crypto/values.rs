@@ -0,0 +1,192 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::collections::HashMap;
+
+use crate::configuration::parser::dotenv;
+
+// The data key encrypting the values is kept in a comment, encrypted like the whole file with the
+// passphrase or to the recipients.
+const DATA_KEY_PREFIX: &str = "# envm-data-key:";
+
+// Whether the environment has its values encrypted, the keys and comments stay in plaintext.
+pub fn has_encrypted_values(content: &str) -> bool {
+    wrapped_data_key(content).is_some()
+}
+
+pub fn is_encrypted_value(value: &str) -> bool {
+    value.starts_with("ENC[") && value.ends_with(']')
+}
+
+pub fn wrapped_data_key(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find_map(|it| it.trim().strip_prefix(DATA_KEY_PREFIX))
+        .map(|it| it.trim().to_string())
+}
+
+// A random 256 bits key in base64.
+pub fn generate_data_key() -> String {
+    let mut key = [0u8; 32];
+    getrandom::getrandom(&mut key).unwrap();
+    STANDARD.encode(key)
+}
+
+// Encrypt each non-empty value as `KEY=ENC[...]`, with the key as the associated data so a
+// ciphertext cannot be moved to another key. The values which are the same as in the previous
+// content keep their ciphertexts, so the diff only shows the changed variables.
+pub fn encrypt_values(
+    content: &str,
+    data_key: &str,
+    wrapped: &str,
+    previous: Option<&str>,
+) -> Result<String, String> {
+    let cipher = cipher(data_key)?;
+    let mut previous_values = HashMap::new();
+    for entry in dotenv::entries(previous.unwrap_or_default()) {
+        if is_encrypted_value(&entry.value) {
+            if let Ok(value) = decrypt_value(&cipher, &entry.key, &entry.value) {
+                previous_values.insert((entry.key, value), entry.value);
+            }
+        }
+    }
+    let mut lines = vec![];
+    for line in content.lines() {
+        if line.trim().starts_with(DATA_KEY_PREFIX) {
+            continue;
+        }
+        match dotenv::parse_line(line) {
+            Some((key, value)) if !value.is_empty() => {
+                let encrypted = match previous_values.remove(&(key.clone(), value.clone())) {
+                    Some(it) => it,
+                    None => encrypt_value(&cipher, &key, &value)?,
+                };
+                lines.push(format!("{}={}", key, encrypted));
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines.push(format!("{} {}", DATA_KEY_PREFIX, wrapped));
+    Ok(join(lines, content))
+}
+
+// Decrypt the values back to the plaintext environment, the data key comment is removed.
+pub fn decrypt_values(content: &str, data_key: &str) -> Result<String, String> {
+    let cipher = cipher(data_key)?;
+    let mut lines = vec![];
+    for line in content.lines() {
+        if line.trim().starts_with(DATA_KEY_PREFIX) {
+            continue;
+        }
+        match dotenv::parse_line(line) {
+            Some((key, value)) if is_encrypted_value(&value) => {
+                lines.push(format!("{}={}", key, decrypt_value(&cipher, &key, &value)?));
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    Ok(join(lines, content))
+}
+
+fn cipher(data_key: &str) -> Result<XChaCha20Poly1305, String> {
+    let key = STANDARD
+        .decode(data_key.trim())
+        .map_err(|err| format!("invalid data key: {}", err))?;
+    XChaCha20Poly1305::new_from_slice(&key).map_err(|_| String::from("invalid data key length"))
+}
+
+fn encrypt_value(cipher: &XChaCha20Poly1305, key: &str, value: &str) -> Result<String, String> {
+    let mut nonce = [0u8; 24];
+    getrandom::getrandom(&mut nonce).unwrap();
+    let payload = Payload {
+        msg: value.as_bytes(),
+        aad: key.as_bytes(),
+    };
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), payload)
+        .map_err(|_| format!("cannot encrypt the value of {}", key))?;
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend(ciphertext);
+    Ok(format!("ENC[{}]", STANDARD.encode(encrypted)))
+}
+
+fn decrypt_value(cipher: &XChaCha20Poly1305, key: &str, value: &str) -> Result<String, String> {
+    let invalid = || format!("cannot decrypt the value of {}", key);
+    let encrypted = STANDARD
+        .decode(&value[4..value.len() - 1])
+        .map_err(|_| invalid())?;
+    if encrypted.len() < 24 {
+        return Err(invalid());
+    }
+    let (nonce, ciphertext) = encrypted.split_at(24);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: key.as_bytes(),
+    };
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .map_err(|_| invalid())?;
+    String::from_utf8(plaintext).map_err(|_| invalid())
+}
+
+// Join the lines with the line ending of the original content, and keep its final newline.
+fn join(lines: Vec<String>, original: &str) -> String {
+    let line_ending = if original.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut content = lines.join(line_ending);
+    if original.is_empty() || original.ends_with('\n') {
+        content.push_str(line_ending);
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_encrypt_and_decrypt_values() -> Result<(), String> {
+        let data_key = generate_data_key();
+        let content = "# the database\nDB_PASSWORD='s3cret'\nEMPTY=\nPORT=5432\n";
+
+        let encrypted = encrypt_values(content, &data_key, "wrapped", None)?;
+
+        assert!(has_encrypted_values(&encrypted));
+        assert!(!encrypted.contains("s3cret"));
+        assert!(encrypted.starts_with("# the database\nDB_PASSWORD=ENC["));
+        assert!(encrypted.contains("\nEMPTY=\nPORT=ENC["));
+        assert!(encrypted.ends_with("# envm-data-key: wrapped\n"));
+        assert_eq!(wrapped_data_key(&encrypted).as_deref(), Some("wrapped"));
+        assert_eq!(decrypt_values(&encrypted, &data_key)?, content);
+        assert!(decrypt_values(&encrypted, &generate_data_key()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_unchanged_ciphertexts() -> Result<(), String> {
+        let data_key = generate_data_key();
+        let previous = encrypt_values("A=1\nB=2\n", &data_key, "wrapped", None)?;
+
+        let encrypted = encrypt_values("A=1\nB=3\n", &data_key, "wrapped", Some(&previous))?;
+
+        let previous_lines: Vec<&str> = previous.lines().collect();
+        let lines: Vec<&str> = encrypted.lines().collect();
+        assert_eq!(lines[0], previous_lines[0]);
+        assert_ne!(lines[1], previous_lines[1]);
+        assert_eq!(decrypt_values(&encrypted, &data_key)?, "A=1\nB=3\n");
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_decrypt_moved_value() -> Result<(), String> {
+        let data_key = generate_data_key();
+        let encrypted = encrypt_values("A=1\n", &data_key, "wrapped", None)?;
+
+        assert!(decrypt_values(&encrypted.replace("A=", "B="), &data_key).is_err());
+        Ok(())
+    }
+}
@@ -8,14 +8,57 @@ pub enum EnvmError {
     MissngHeadFile,
     MissingTargetEnvironment(String),
     FailedToBackupLocalEnvironment,
+    FailedToBackupEnvironment,
     MissingBackupEnvironment,
     RepositoryAlreadyExists,
     MissingTemplateEnvironment(String),
+    TemplateEnvironmentAlreadyExists(String),
+    MissingEnvironments,
     TargetEnvironmentAlreadyExists(String),
     AlreadyUsingTargetEnvironment(String),
     RemovingUsingEnvironment(String),
     UnableReadGitignore(std::io::Error),
     UnableWriteGitignore(std::io::Error),
+    InvalidSearchPattern(regex::Error),
+    VerificationFailed(Vec<String>),
+    FailedToParseSchema(String),
+    RefuseToUseEnvironment(String),
+    InvalidPlaceholderPattern(regex::Error),
+    LintFailed(usize),
+    NotFormatted(Vec<String>),
+    SecretsFound(usize),
+    FailedToWriteAuditLog(std::io::Error),
+    FailedToReadFingerprint(String),
+    FingerprintMismatch(String),
+    FailedToReadPassphrase(String),
+    FailedToEncryptEnvironment(String, String),
+    FailedToDecryptEnvironment(String, String),
+    CannotEncryptLocalEnvironment,
+    EnvironmentAlreadyEncrypted(String),
+    EnvironmentNotEncrypted(String),
+    FailedToReadIdentity(String),
+    InvalidRecipient(String),
+    RecipientAlreadyExists(String),
+    MissingRecipient(String),
+    FailedToStartAgent(String),
+    MissingAgent,
+    FailedToConnectAgent(String),
+    FailedToReadSigningKey(String),
+    InvalidSignatures(String),
+    FailedToResolveReferences(Vec<(String, String)>),
+    FailedToInterpolate(Vec<(String, String)>),
+    FailedToInclude(String, String),
+    MissingParentEnvironment(String, String),
+    CyclicParentEnvironments(Vec<String>),
+    MissingVariable(String, String),
+    InvalidEnvironmentStack(String, String),
+    MissingDimension(String),
+    SharedLocalEnvironment(String),
+    ModifiedEnvironment(String),
+    MissingRecipients(String),
+    InvalidEnvironmentName(String, String),
+    PlaintextEnvironmentExists(String),
+    FailedToRunCommand(String, String),
 }
 
 impl fmt::Display for EnvmError {
@@ -34,12 +77,25 @@ impl fmt::Display for EnvmError {
             EnvmError::FailedToBackupLocalEnvironment => {
                 write!(f, "failed to backup the local environment")
             }
+            EnvmError::FailedToBackupEnvironment => {
+                write!(f, "failed to backup the environment")
+            }
             EnvmError::MissingBackupEnvironment => {
                 write!(f, "cannot found the backup local environment")
             }
             EnvmError::RepositoryAlreadyExists => write!(f, "the envm repository already exists"),
             EnvmError::MissingTemplateEnvironment(filename) => {
-                write!(f, "cannot found the template environment: {}", filename)
+                write!(
+                    f,
+                    "cannot found the template environment: {}, use `envm template generate` to create it",
+                    filename
+                )
+            }
+            EnvmError::TemplateEnvironmentAlreadyExists(filename) => {
+                write!(f, "the template environment already exists: {}", filename)
+            }
+            EnvmError::MissingEnvironments => {
+                write!(f, "cannot found any environment")
             }
             EnvmError::TargetEnvironmentAlreadyExists(env) => {
                 write!(f, "the '{}' environment already exists", env)
@@ -56,6 +112,157 @@ impl fmt::Display for EnvmError {
             EnvmError::UnableWriteGitignore(err) => {
                 write!(f, "unable to write to the .gitignore file: {}", err)
             }
+            EnvmError::VerificationFailed(envs) => {
+                write!(f, "verification failed: {}", envs.join(", "))
+            }
+            EnvmError::RefuseToUseEnvironment(env) => {
+                write!(
+                    f,
+                    "refuse to use '{}' environment, fix the problems above or use --force",
+                    env
+                )
+            }
+            EnvmError::FailedToReadIdentity(err) => {
+                write!(f, "failed to read the identity: {}", err)
+            }
+            EnvmError::InvalidRecipient(err) => write!(f, "{}", err),
+            EnvmError::RecipientAlreadyExists(recipient) => {
+                write!(f, "recipient '{}' already exists", recipient)
+            }
+            EnvmError::FailedToInterpolate(problems) => {
+                write!(f, "failed to interpolate the variables:")?;
+                for (key, problem) in problems {
+                    write!(f, "\n  {}: {}", key, problem)?;
+                }
+                Ok(())
+            }
+            EnvmError::FailedToResolveReferences(errors) => {
+                write!(f, "failed to resolve the references:")?;
+                for (key, err) in errors {
+                    write!(f, "\n  {}: {}", key, err)?;
+                }
+                Ok(())
+            }
+            EnvmError::FailedToReadSigningKey(err) => {
+                write!(f, "failed to read the signing key: {}", err)
+            }
+            EnvmError::InvalidSignatures(err) => write!(
+                f,
+                "invalid signatures at .envm/signatures: {}, run `envm sign` to sign all environments again",
+                err
+            ),
+            EnvmError::FailedToStartAgent(err) => write!(f, "failed to start the agent: {}", err),
+            EnvmError::MissingAgent => write!(
+                f,
+                "cannot found the agent, run `eval \"$(envm agent start)\"` to start one"
+            ),
+            EnvmError::FailedToConnectAgent(err) => {
+                write!(f, "failed to connect to the agent: {}", err)
+            }
+            EnvmError::MissingRecipient(recipient) => {
+                write!(f, "cannot found the recipient '{}'", recipient)
+            }
+            EnvmError::MissingRecipients(env) => write!(
+                f,
+                "cannot found any recipient for '{}' environment, which is encrypted to recipients, add one with `envm recipients add` or decrypt it first",
+                env
+            ),
+            EnvmError::FailedToReadPassphrase(err) => {
+                write!(f, "failed to read the passphrase: {}", err)
+            }
+            EnvmError::FailedToEncryptEnvironment(env, err) => {
+                write!(f, "failed to encrypt '{}' environment: {}", env, err)
+            }
+            EnvmError::FailedToInclude(env, err) => {
+                write!(f, "failed to include files in '{}' environment: {}", env, err)
+            }
+            EnvmError::MissingParentEnvironment(env, parent) => {
+                write!(
+                    f,
+                    "cannot found the {} environment, the parent of '{}' environment",
+                    parent, env
+                )
+            }
+            EnvmError::CyclicParentEnvironments(envs) => {
+                write!(f, "cyclic parent environments: {}", envs.join(" -> "))
+            }
+            EnvmError::FailedToRunCommand(command, err) => {
+                write!(f, "failed to run `{}`: {}", command, err)
+            }
+            EnvmError::PlaintextEnvironmentExists(env) => write!(
+                f,
+                "cannot decrypt '{}' environment, its plaintext file already exists, remove it first",
+                env
+            ),
+            EnvmError::InvalidEnvironmentName(env, dimensions) => write!(
+                f,
+                "invalid '{}' environment, it should be {} with a value for each placeholder of the pattern",
+                env, dimensions
+            ),
+            EnvmError::MissingDimension(dimension) => {
+                write!(f, "cannot found {{{}}} in the pattern", dimension)
+            }
+            EnvmError::SharedLocalEnvironment(env) => {
+                write!(
+                    f,
+                    "the '{}' environments share the same local file, add the other placeholders to `local` like `.env.{{service}}`",
+                    env
+                )
+            }
+            EnvmError::ModifiedEnvironment(env) => write!(
+                f,
+                "the {} environment is modified outside envm, review it and run `envm sign {}` first",
+                env, env
+            ),
+            EnvmError::InvalidEnvironmentStack(env, err) => {
+                write!(f, "invalid environment stack '{}': {}", env, err)
+            }
+            EnvmError::MissingVariable(key, env) => {
+                write!(f, "cannot found {} in '{}' environment", key, env)
+            }
+            EnvmError::FailedToDecryptEnvironment(env, err) => {
+                write!(f, "failed to decrypt '{}' environment: {}", env, err)
+            }
+            EnvmError::CannotEncryptLocalEnvironment => {
+                write!(f, "cannot encrypt the local environment")
+            }
+            EnvmError::EnvironmentAlreadyEncrypted(env) => {
+                write!(f, "'{}' environment is already encrypted", env)
+            }
+            EnvmError::EnvironmentNotEncrypted(env) => {
+                write!(f, "'{}' environment is not encrypted", env)
+            }
+            EnvmError::FailedToReadFingerprint(err) => {
+                write!(f, "failed to read the fingerprint: {}", err)
+            }
+            EnvmError::FingerprintMismatch(env) => {
+                write!(f, "'{}' environment doesn't match the fingerprint", env)
+            }
+            EnvmError::FailedToWriteAuditLog(err) => {
+                write!(f, "failed to write the audit log: {}", err)
+            }
+            EnvmError::SecretsFound(count) => {
+                write!(f, "found {} secret problem(s)", count)
+            }
+            EnvmError::NotFormatted(files) => {
+                write!(
+                    f,
+                    "{} not formatted, run `envm fmt` to format",
+                    files.join(", ")
+                )
+            }
+            EnvmError::LintFailed(count) => {
+                write!(f, "found {} lint error(s)", count)
+            }
+            EnvmError::InvalidPlaceholderPattern(err) => {
+                write!(f, "invalid placeholder pattern in configuration: {}", err)
+            }
+            EnvmError::FailedToParseSchema(err) => {
+                write!(f, "failed to parse the schema: {}", err)
+            }
+            EnvmError::InvalidSearchPattern(err) => {
+                write!(f, "invalid search pattern: {}", err)
+            }
         }
     }
 }
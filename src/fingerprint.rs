@@ -0,0 +1,185 @@
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+use crate::configuration::parser::dotenv;
+
+const HEADER: &str = "envm-fingerprint v1";
+
+// The salted hashes of the values of an environment, which can be shared to check whether two
+// environments have the same values without revealing them. The salt is printed in the output so
+// the other side can hash their values with the same salt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fingerprint {
+    salt: String,
+    hashes: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    // The key only exists in our environment.
+    Added(String),
+    // The key only exists in the other fingerprint.
+    Missing(String),
+    Changed(String),
+}
+
+impl Fingerprint {
+    pub fn new(variables: &HashMap<String, String>) -> Fingerprint {
+        let mut salt = [0u8; 16];
+        getrandom::getrandom(&mut salt).unwrap();
+        Fingerprint::with_salt(variables, &to_hex(&salt))
+    }
+
+    pub fn with_salt(variables: &HashMap<String, String>, salt: &str) -> Fingerprint {
+        let hashes = variables
+            .iter()
+            .map(|(key, value)| {
+                let hash = hash(&[salt, key, &dotenv::unquote(value)]);
+                (key.clone(), hash)
+            })
+            .collect();
+        Fingerprint {
+            salt: salt.to_string(),
+            hashes,
+        }
+    }
+
+    pub fn salt(&self) -> &str {
+        &self.salt
+    }
+
+    // The digest of all keys and values, two environments are the same if the digests are equal.
+    pub fn digest(&self) -> String {
+        let lines: Vec<String> = self
+            .hashes
+            .iter()
+            .map(|(key, hash)| format!("{}={}", key, hash))
+            .collect();
+        hash(&[&self.salt, &lines.join("\n")])
+    }
+
+    pub fn parse(content: &str) -> Result<Fingerprint, String> {
+        let mut lines = content.lines().map(str::trim).filter(|it| !it.is_empty());
+        if lines.next() != Some(HEADER) {
+            return Err(format!("expected `{}` at the first line", HEADER));
+        }
+        let mut salt = None;
+        let mut digest = None;
+        let mut hashes = BTreeMap::new();
+        for line in lines {
+            match line.split_once(' ') {
+                Some(("salt", value)) => salt = Some(value.trim().to_string()),
+                Some(("digest", value)) => digest = Some(value.trim().to_string()),
+                Some(("key", value)) => match value.trim().split_once(' ') {
+                    Some((key, hash)) => {
+                        hashes.insert(key.to_string(), hash.trim().to_string());
+                    }
+                    None => return Err(format!("invalid line `{}`", line)),
+                },
+                _ => return Err(format!("invalid line `{}`", line)),
+            }
+        }
+        let fingerprint = Fingerprint {
+            salt: salt.ok_or_else(|| String::from("missing salt"))?,
+            hashes,
+        };
+        if digest.as_deref() != Some(fingerprint.digest().as_str()) {
+            return Err(String::from("the digest doesn't match the keys"));
+        }
+        Ok(fingerprint)
+    }
+
+    // List the keys which are different from the other fingerprint, both should use the same salt.
+    pub fn compare(&self, other: &Fingerprint) -> Vec<Difference> {
+        let mut differences = vec![];
+        for (key, hash) in &self.hashes {
+            match other.hashes.get(key) {
+                None => differences.push(Difference::Added(key.clone())),
+                Some(other) if other != hash => differences.push(Difference::Changed(key.clone())),
+                _ => (),
+            }
+        }
+        for key in other.hashes.keys() {
+            if !self.hashes.contains_key(key) {
+                differences.push(Difference::Missing(key.clone()));
+            }
+        }
+        differences
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "salt {}", self.salt)?;
+        writeln!(f, "digest {}", self.digest())?;
+        for (key, hash) in &self.hashes {
+            writeln!(f, "key {} {}", key, hash)?;
+        }
+        Ok(())
+    }
+}
+
+// The parts are separated by NUL so that moving characters between them changes the hash.
+fn hash(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for (index, it) in parts.iter().enumerate() {
+        if index > 0 {
+            hasher.update([0u8]);
+        }
+        hasher.update(it.as_bytes());
+    }
+    to_hex(&hasher.finalize())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|it| format!("{:02x}", it)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compare_fingerprints() -> Result<(), String> {
+        let ours = dotenv::parse("A=1\nB='secret'\nC=3");
+        let theirs = dotenv::parse("A=1\nB=secret\nC=4\nD=5");
+
+        let their_fingerprint = Fingerprint::parse(&Fingerprint::new(&theirs).to_string())?;
+        let our_fingerprint = Fingerprint::with_salt(&ours, their_fingerprint.salt());
+
+        assert_eq!(
+            our_fingerprint.compare(&their_fingerprint),
+            vec![
+                Difference::Changed(String::from("C")),
+                Difference::Missing(String::from("D"))
+            ]
+        );
+        assert_ne!(our_fingerprint.digest(), their_fingerprint.digest());
+        assert_eq!(
+            Fingerprint::with_salt(&theirs, their_fingerprint.salt()).digest(),
+            their_fingerprint.digest()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_reveal_values() {
+        let variables = dotenv::parse("TOKEN=s3cret");
+        let output = Fingerprint::new(&variables).to_string();
+
+        assert!(!output.contains("s3cret"));
+        assert_ne!(output, Fingerprint::new(&variables).to_string());
+    }
+
+    #[test]
+    fn should_not_parse_tampered_fingerprint() {
+        let variables = dotenv::parse("A=1");
+        let output = Fingerprint::with_salt(&variables, "00").to_string();
+
+        assert!(Fingerprint::parse(&output).is_ok());
+        assert!(Fingerprint::parse(&output.replace("key A", "key B")).is_err());
+        assert!(Fingerprint::parse("A=1").is_err());
+    }
+}
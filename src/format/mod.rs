@@ -0,0 +1,194 @@
+use crate::configuration::document::Document;
+use crate::configuration::parser::dotenv;
+use crate::lint;
+
+// Format the dotenv file in the canonical style:
+//
+// - `KEY=value` without indentation and spaces around `=`
+// - the value is unquoted when it is safe, otherwise double quoted
+// - no trailing whitespace, no repeated blank lines, LF line endings and a final newline
+//
+// The value is kept as it is when changing the quotes may change its meaning, for example the
+// single quoted value contains `$` or the unquoted value contains ` #`.
+pub fn format(content: &str) -> String {
+    let mut lines: Vec<String> = vec![];
+    for line in content.lines() {
+        let line = line.trim();
+        // Skip the leading and repeated blank lines.
+        if line.is_empty() && lines.last().map(|it| it.is_empty()).unwrap_or(true) {
+            continue;
+        }
+        let line = match dotenv::parse_line(line) {
+            Some((key, value)) => format!("{}={}", key, format_value(&value)),
+            None => line.to_string(),
+        };
+        lines.push(line);
+    }
+    while matches!(lines.last(), Some(it) if it.is_empty()) {
+        lines.pop();
+    }
+    if lines.is_empty() {
+        return String::new();
+    }
+    lines.join("\n") + "\n"
+}
+
+fn format_value(value: &str) -> String {
+    if !lint::is_balanced(value) {
+        return value.to_string();
+    }
+    match value.chars().next() {
+        Some('"') if value.ends_with('"') => {
+            let unquoted = dotenv::unquote(value);
+            if is_safe(&unquoted) {
+                unquoted
+            } else {
+                value.to_string()
+            }
+        }
+        Some('\'') if value.ends_with('\'') => {
+            let unquoted = dotenv::unquote(value);
+            if is_safe(&unquoted) {
+                unquoted
+            } else if !unquoted.contains(['$', '\\', '"', '\n']) {
+                format!("\"{}\"", unquoted)
+            } else {
+                value.to_string()
+            }
+        }
+        // The quoted value followed by a comment.
+        Some('"') | Some('\'') => value.to_string(),
+        _ => {
+            if value.contains(char::is_whitespace) && !value.contains(['#', '"', '\'', '\\']) {
+                lint::quote(value)
+            } else {
+                value.to_string()
+            }
+        }
+    }
+}
+
+// Write the value in the canonical style, quoted only when it has unsafe characters.
+pub fn quote_if_needed(value: &str) -> String {
+    if is_safe(value) {
+        value.to_string()
+    } else {
+        lint::quote(value)
+    }
+}
+
+fn is_safe(value: &str) -> bool {
+    value
+        .chars()
+        .all(|it| it.is_ascii_alphanumeric() || "_./:@%+,=~-".contains(it))
+}
+
+// Reorder the variables to follow the template, the comments between the variables in template
+// are used as the section comments. The variables not in template are put at the end, with the
+// comments which don't describe any variable. Every definition of a duplicated key is kept in
+// its original order, so reordering never changes which value wins.
+pub fn reorder(content: &str, template: &Document) -> String {
+    let document = Document::parse(content);
+    let mut lines: Vec<String> = vec![];
+    let mut described = vec![];
+    let template_keys = template.keys();
+    for key in &template_keys {
+        described.extend(template.comment_block(key));
+    }
+    let mut section: Vec<String> = vec![];
+    for line in template.lines() {
+        match dotenv::parse_line(line) {
+            Some((key, _)) => {
+                if !document.contains_key(&key) {
+                    section.clear();
+                    continue;
+                }
+                lines.append(&mut section);
+                for (nth, index) in document.positions(&key).into_iter().enumerate() {
+                    let comment_block = document.comment_block_at(index);
+                    if comment_block.is_empty() && nth == 0 {
+                        lines.extend(template.comment_block(&key));
+                    } else {
+                        lines.extend(comment_block);
+                    }
+                    lines.push(document.lines()[index].clone());
+                }
+            }
+            None if line.trim().is_empty() => section.push(String::new()),
+            None if !described.contains(line) => section.push(line.clone()),
+            None => (),
+        }
+    }
+
+    let mut rest: Vec<String> = vec![];
+    let mut attached: Vec<String> = vec![];
+    for (index, line) in document.lines().iter().enumerate() {
+        if dotenv::parse_line(line).is_some() {
+            attached.extend(document.comment_block_at(index));
+        }
+    }
+    for (index, line) in document.lines().iter().enumerate() {
+        match dotenv::parse_line(line) {
+            Some((key, _)) if template_keys.contains(&key) => (),
+            Some(_) => {
+                rest.extend(document.comment_block_at(index));
+                rest.push(line.clone());
+            }
+            None if line.trim().starts_with('#') && !attached.contains(line) => {
+                rest.push(line.clone())
+            }
+            None => (),
+        }
+    }
+    if !rest.is_empty() {
+        lines.push(String::new());
+        lines.extend(rest);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_format_in_canonical_style() {
+        let content = "\n  # app \r\n  APP_URL = \"https://example.com\"  \r\n\r\n\r\nNAME='my app'\r\nPORT='3000'\r\nGREETING=hello world\r\nPRICE='$5'\r\nNOTE=a # b\r\nEMPTY=\"\"";
+        assert_eq!(
+            format(content),
+            "# app\nAPP_URL=https://example.com\n\nNAME=\"my app\"\nPORT=3000\nGREETING=\"hello world\"\nPRICE='$5'\nNOTE=a # b\nEMPTY=\n"
+        );
+    }
+
+    #[test]
+    fn should_be_idempotent() {
+        let content = "A = 'x y'\nB=\"a \\\"b\\\"\"\n";
+        let formatted = format(content);
+        assert_eq!(formatted, "A=\"x y\"\nB=\"a \\\"b\\\"\"\n");
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn should_reorder_by_template() {
+        let template = Document::parse("# app\nAPP_URL=\nPORT=\n\n# db\n# the host\nDB_HOST=\n");
+        let content =
+            "# local only\nDEBUG=true\nDB_HOST=db\n# my port\nPORT=80\nAPP_URL=x\n# note\n";
+
+        assert_eq!(
+            format(&reorder(content, &template)),
+            "# app\nAPP_URL=x\n# my port\nPORT=80\n\n# db\n# the host\nDB_HOST=db\n\n# local only\nDEBUG=true\n# note\n"
+        );
+    }
+
+    #[test]
+    fn should_keep_duplicated_keys_on_reorder() {
+        let template = Document::parse("APP_URL=\nPORT=\n");
+        let content =
+            "# old port\nPORT=80\nAPP_URL=x\n# new port\nPORT=8080\nDEBUG=1\n# again\nDEBUG=2\n";
+
+        assert_eq!(
+            format(&reorder(content, &template)),
+            "APP_URL=x\n# old port\nPORT=80\n# new port\nPORT=8080\n\nDEBUG=1\n# again\nDEBUG=2\n"
+        );
+    }
+}
@@ -1,55 +1,123 @@
+mod agent;
 mod command;
 mod configuration;
+mod crypto;
 mod error;
+mod fingerprint;
+mod format;
 mod gitignore;
+mod lint;
+mod redaction;
+mod reference;
 mod repository;
+mod signature;
 
 use crate::command::{Command, UseCase};
+use crate::configuration::parser::dotenv;
 use crate::error::EnvmError;
+use crate::fingerprint::{Difference, Fingerprint};
 use crate::gitignore::Gitignore;
+use crate::lint::secrets::Finding;
+use crate::lint::Severity;
+use crate::redaction::Redactor;
+use crate::repository::environment::{EnvType, Matrix};
+use crate::repository::path;
+use crate::repository::verification::Verification;
 use crate::repository::Repository;
 use colored::Colorize;
-use std::env;
+use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::process::{self, Stdio};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
 
 pub fn run() -> Result<(), EnvmError> {
     let current_dir = env::current_dir().unwrap();
     let command = Command::new();
-    let use_case = command.run();
+    let (use_case, options) = command.run();
     match use_case {
         UseCase::InitConfiguration => {
             let repo = Repository::new(current_dir.clone());
             let repo_path = repo.init()?;
             println!("initialized envm repository in {}", repo_path.display());
         }
+        // The agent is not bound to any repository.
+        UseCase::StartAgent(lifetime, foreground, socket) => {
+            let socket = match socket {
+                Some(it) => PathBuf::from(it),
+                None => agent::socket_path().map_err(EnvmError::FailedToStartAgent)?,
+            };
+            if foreground {
+                print_agent_socket(&socket);
+                agent::serve(&socket, Duration::from_secs(lifetime))
+                    .map_err(EnvmError::FailedToStartAgent)?;
+            } else {
+                start_agent(&socket, lifetime)?;
+                print_agent_socket(&socket);
+            }
+        }
+        UseCase::LockAgent => {
+            agent::Client::from_env()
+                .ok_or(EnvmError::MissingAgent)?
+                .lock()
+                .map_err(EnvmError::FailedToConnectAgent)?;
+            println!("the agent is locked");
+        }
+        UseCase::StopAgent => {
+            agent::Client::from_env()
+                .ok_or(EnvmError::MissingAgent)?
+                .stop()
+                .map_err(EnvmError::FailedToConnectAgent)?;
+            println!("unset {};", agent::SOCKET_VARIABLE);
+        }
         other => {
             let repo = Repository::load(current_dir.clone())?;
+            if options.reveal && prints_values(&other) {
+                repo.audit(&format!("reveal {}", options.command))?;
+            }
+            let redactor = repo.redactor(options.reveal)?;
             match other {
                 UseCase::DiffEnvironment(target) => {
-                    let (missing, extra) = repo.compare_to_template(&target);
-                    if let Some(missing) = missing {
-                        println!("missing variables:");
-                        missing
-                            .iter()
-                            .map(|it| format!("- {}", it).red())
-                            .for_each(|it| println!("{}", it));
+                    let verification = repo.verify_environment(&target)?;
+                    print_verification(&verification, &redactor);
+                }
+                UseCase::VerifyEnvironments(envs, fail_on_placeholder) => {
+                    let mut failed = vec![];
+                    for env in target_environments(&repo, envs) {
+                        let verification = repo.verify_environment(&env)?;
+                        let is_ok = verification.is_ok()
+                            && (!fail_on_placeholder || verification.placeholders.is_empty());
+                        if is_ok {
+                            println!("{} '{}' environment", "ok".green(), env);
+                            print_placeholders(&verification);
+                            print_signature(&verification);
+                        } else {
+                            println!("{} '{}' environment", "failed".red(), env);
+                            print_verification(&verification, &redactor);
+                            failed.push(env);
+                        }
                     }
-                    if let Some(extra) = extra {
-                        println!("extra variables:");
-                        extra
-                            .iter()
-                            .map(|it| format!("+ {}", it).green())
-                            .for_each(|it| println!("{}", it));
+                    if !failed.is_empty() {
+                        return Err(EnvmError::VerificationFailed(failed));
                     }
                 }
-                UseCase::UseEnvironment(target) => {
-                    repo.use_environment(&target)?;
-                    println!("switch to {} environment", target);
+                UseCase::UseEnvironment(target, force) => {
+                    use_environment(&repo, &target, force, &redactor)?;
+                }
+                UseCase::UseStage(stage, force) => {
+                    let target = repo.stage_selection(&stage)?;
+                    use_environment(&repo, &target, force, &redactor)?;
                 }
                 UseCase::NewEnvironment(env) => {
                     repo.new_environment(&env)?;
                     println!("create a new environment '{}'", env);
                 }
-                UseCase::ListEnvironments => {
+                UseCase::ListEnvironments(matrix)
+                    if matrix && repo.config().dimensions().len() > 1 =>
+                {
+                    print_matrix(&repo.environment_matrix());
+                }
+                UseCase::ListEnvironments(_) => {
                     for env in repo.list_environments() {
                         println!("{}", env);
                     }
@@ -58,32 +126,567 @@ pub fn run() -> Result<(), EnvmError> {
                     repo.remove_environment(&env)?;
                     println!("removed environment '{}'", env);
                 }
+                UseCase::ShowStatus => {
+                    let current = repo.current_env();
+                    print_current_env(&repo);
+                    let envs = match current {
+                        EnvType::Selection(_) => repo.select(current.to_string()),
+                        _ => vec![String::from(current.to_string())],
+                    };
+                    for env in &envs {
+                        if envs.len() > 1 {
+                            println!("'{}' environment:", env);
+                        }
+                        match repo.verify_environment(env) {
+                            Ok(verification) if verification.is_clean() => {
+                                println!("the environment matches the template");
+                            }
+                            Ok(verification) => print_verification(&verification, &redactor),
+                            Err(EnvmError::MissingTemplateEnvironment(template)) => {
+                                println!("no template to compare with: {}", template);
+                                break;
+                            }
+                            Err(err) => return Err(err),
+                        }
+                    }
+                }
                 UseCase::ShowCurrentUsingEnvironment => {
-                    println!(
-                        "currently using '{}' environment",
-                        repo.current_env().to_string()
-                    );
+                    print_current_env(&repo);
                 }
                 UseCase::Gitignore => {
                     let gitignore_path = current_dir.join(".gitignore");
                     let mut gitignore = Gitignore::load(gitignore_path)?;
                     let config = repo.config();
-                    let gitignore_pattern = config.pattern().replace("{}", "*");
+                    let gitignore_pattern = path::to_glob(config.pattern());
                     let env_template = format!("!{}", config.template());
+                    let encrypted_envs = format!("!{}.enc", path::to_glob(config.pattern()));
 
                     let patterns = vec![
-                        ".envm".to_string(),
-                        config.local().clone(),
+                        // The recipients are shared by the team, so only they are committed.
+                        ".envm/*".to_string(),
+                        "!.envm/recipients".to_string(),
+                        path::to_glob(config.local()),
                         gitignore_pattern,
                         env_template,
+                        encrypted_envs,
                     ];
+                    // The whole `.envm` ignored by the older versions cannot have exceptions.
+                    gitignore.remove_pattern(".envm");
                     gitignore.ignore_patterns_section("envm", patterns);
                     gitignore.save()?;
                     println!("updated .gitignore with .envm and patterns from configuration");
                 }
+                UseCase::SearchEnvironments(pattern, scope, show_values) => {
+                    let re = Regex::new(&pattern).map_err(EnvmError::InvalidSearchPattern)?;
+                    for it in repo.search(&re, scope) {
+                        let value = if show_values {
+                            redactor.redact(&it.key, &it.value)
+                        } else {
+                            String::from("*****")
+                        };
+                        println!(
+                            "{}:{}: {}={}",
+                            it.source.cyan(),
+                            it.line.to_string().yellow(),
+                            it.key.bold(),
+                            value
+                        );
+                    }
+                }
+                UseCase::SyncEnvironments(envs, dry_run) => {
+                    for env in target_environments(&repo, envs) {
+                        let inserted = repo.sync_environment(&env, dry_run)?;
+                        if inserted.is_empty() {
+                            println!("'{}' environment is up to date", env);
+                            continue;
+                        }
+                        if dry_run {
+                            println!("would add to '{}' environment:", env);
+                        } else {
+                            println!("added to '{}' environment:", env);
+                        }
+                        inserted
+                            .iter()
+                            .map(|it| format!("+ {}", redact_line(it, &redactor)).green())
+                            .for_each(|it| println!("{}", it));
+                    }
+                }
+                UseCase::PruneEnvironments(envs, dry_run) => {
+                    let mut backup = false;
+                    for env in target_environments(&repo, envs) {
+                        let removed = repo.prune_environment(&env, dry_run)?;
+                        if removed.is_empty() {
+                            println!("'{}' environment has no extra variables", env);
+                            continue;
+                        }
+                        backup = !dry_run;
+                        if dry_run {
+                            println!("would remove from '{}' environment:", env);
+                        } else {
+                            println!("removed from '{}' environment:", env);
+                        }
+                        removed
+                            .iter()
+                            .map(|it| format!("- {}", it).red())
+                            .for_each(|it| println!("{}", it));
+                    }
+                    if backup {
+                        println!("the environments before pruning are saved in .envm/backups");
+                    }
+                }
+                UseCase::GenerateTemplate(update, placeholder) => {
+                    let written = repo.generate_template(update, &placeholder)?;
+                    let template = repo.config().template();
+                    if written.is_empty() {
+                        println!("{} is up to date", template);
+                    } else if update {
+                        println!("added to {}:", template);
+                    } else {
+                        println!("generated {}:", template);
+                    }
+                    written
+                        .iter()
+                        .map(|it| format!("+ {}", redact_line(it, &redactor)).green())
+                        .for_each(|it| println!("{}", it));
+                }
+                UseCase::LintEnvironments(fix) => {
+                    let mut errors = 0;
+                    for (file, problems) in repo.lint_files(fix)? {
+                        for it in problems {
+                            let severity = match it.severity {
+                                Severity::Error => {
+                                    errors += 1;
+                                    "error".red()
+                                }
+                                _ => "warning".yellow(),
+                            };
+                            println!(
+                                "{}:{}: {} {}: {}",
+                                file,
+                                it.line,
+                                severity,
+                                it.rule.name(),
+                                it.message
+                            );
+                        }
+                    }
+                    if errors > 0 {
+                        return Err(EnvmError::LintFailed(errors));
+                    }
+                }
+                UseCase::ShowEnvironment(env, raw, process_env) => {
+                    for (key, value, references) in repo.show_environment(&env, raw, process_env)? {
+                        let redacted = redactor.redact_interpolated(&key, &value, &references);
+                        let value = if redacted == value {
+                            format::quote_if_needed(&value)
+                        } else {
+                            redacted
+                        };
+                        println!("{}={}", key, value);
+                    }
+                }
+                UseCase::ExecEnvironment(env, command, force) => {
+                    check_environments(&repo, std::slice::from_ref(&env), force, &redactor)?;
+                    // The variables are only given to the child process.
+                    let variables = repo.show_environment(&env, false, false)?;
+                    let status = process::Command::new(&command[0])
+                        .args(&command[1..])
+                        .envs(variables.into_iter().map(|(key, value, _)| (key, value)))
+                        .status()
+                        .map_err(|err| {
+                            EnvmError::FailedToRunCommand(command.join(" "), err.to_string())
+                        })?;
+                    if !status.success() {
+                        process::exit(status.code().unwrap_or(1));
+                    }
+                }
+                UseCase::ExplainVariable(env, key) => {
+                    let ancestry = repo.ancestry(&env)?;
+                    if ancestry.len() > 1 {
+                        println!("inherits {}", ancestry.join(" -> "));
+                    }
+                    let definitions = repo.explain(&env, &key)?;
+                    for (index, it) in definitions.iter().enumerate() {
+                        let line = format!(
+                            "{}:{}: {}={}",
+                            it.file,
+                            it.line,
+                            key,
+                            redactor.redact(&key, &dotenv::unquote(&it.value))
+                        );
+                        if index + 1 == definitions.len() {
+                            println!("{} {}", line.bold(), "(final)".green());
+                        } else {
+                            println!("{}", line.dimmed());
+                        }
+                    }
+                }
+                UseCase::SignEnvironments(envs) => {
+                    for env in repo.sign_environments(&envs)? {
+                        println!("signed '{}' environment", env);
+                    }
+                }
+                UseCase::FormatEnvironments(envs, reorder, check) => {
+                    let changed = repo.format_files(&envs, reorder, check)?;
+                    if check {
+                        if !changed.is_empty() {
+                            return Err(EnvmError::NotFormatted(changed));
+                        }
+                        println!("all files are formatted");
+                    } else {
+                        for file in changed {
+                            println!("formatted {}", file);
+                        }
+                    }
+                }
+                UseCase::ScanSecrets => {
+                    let findings = repo.scan_secrets()?;
+                    for it in &findings {
+                        match it {
+                            Finding::Reused {
+                                env,
+                                key,
+                                production,
+                                hash,
+                            } => println!(
+                                "{}: {} {} has the same value as '{}' ({})",
+                                env,
+                                "reused".red(),
+                                key.bold(),
+                                production,
+                                hash
+                            ),
+                            Finding::Credential {
+                                env,
+                                key,
+                                reason,
+                                hash,
+                            } => println!(
+                                "{}: {} {} looks like a live credential, {} ({})",
+                                env,
+                                "credential".red(),
+                                key.bold(),
+                                reason,
+                                hash
+                            ),
+                        }
+                    }
+                    if !findings.is_empty() {
+                        return Err(EnvmError::SecretsFound(findings.len()));
+                    }
+                    println!("no secret problems found");
+                }
+                UseCase::EncryptEnvironment(env, values) => {
+                    repo.encrypt_environment(&env, values)?;
+                    println!("encrypted '{}' environment", env);
+                }
+                UseCase::DecryptEnvironment(env) => {
+                    repo.decrypt_environment(&env)?;
+                    println!("decrypted '{}' environment", env);
+                }
+                UseCase::ListRecipients => {
+                    for recipient in repo.recipients() {
+                        println!("{}", recipient);
+                    }
+                }
+                UseCase::AddRecipient(key) => {
+                    for env in repo.add_recipient(&key)? {
+                        println!("encrypted '{}' environment again", env);
+                    }
+                    println!("added recipient {}", key);
+                }
+                UseCase::RemoveRecipient(key) => {
+                    for env in repo.remove_recipient(&key)? {
+                        println!("encrypted '{}' environment again", env);
+                    }
+                    println!("removed recipient {}", key);
+                    println!(
+                        "{}",
+                        "warning: the recipient can still decrypt the old versions of the files"
+                            .yellow()
+                    );
+                }
+                UseCase::Fingerprint(env, None) => {
+                    print!("{}", repo.fingerprint(&env, None)?);
+                }
+                UseCase::Fingerprint(env, Some(file)) => {
+                    let content = fs::read_to_string(&file)
+                        .map_err(|err| EnvmError::FailedToReadFingerprint(err.to_string()))?;
+                    let other =
+                        Fingerprint::parse(&content).map_err(EnvmError::FailedToReadFingerprint)?;
+                    let differences = repo.fingerprint(&env, Some(other.salt()))?.compare(&other);
+                    if differences.is_empty() {
+                        println!("'{}' environment matches the fingerprint", env);
+                        return Ok(());
+                    }
+                    for it in &differences {
+                        let line = match it {
+                            Difference::Added(key) => format!("+ {}", key).green(),
+                            Difference::Missing(key) => format!("- {}", key).red(),
+                            Difference::Changed(key) => format!("~ {}", key).yellow(),
+                        };
+                        println!("{}", line);
+                    }
+                    return Err(EnvmError::FingerprintMismatch(env));
+                }
+                UseCase::ExportSchema => {
+                    println!("{}", repo.export_schema()?);
+                }
                 _ => (),
             }
         }
     };
     Ok(())
 }
+
+fn print_current_env(repo: &Repository) {
+    let env = repo.current_env();
+    match env {
+        EnvType::Stack(_) => {
+            println!(
+                "currently using '{}' environment, stacked from {}",
+                env.to_string(),
+                env.layers().join(" -> ")
+            );
+        }
+        EnvType::Selection(_) => {
+            println!(
+                "currently using '{}' environments: {}",
+                env.to_string(),
+                repo.select(env.to_string()).join(", ")
+            );
+        }
+        _ => println!("currently using '{}' environment", env.to_string()),
+    }
+}
+
+// Only the commands which print the values can reveal them, so the others are not audited.
+fn prints_values(use_case: &UseCase) -> bool {
+    match use_case {
+        UseCase::SearchEnvironments(_, _, show_values) => *show_values,
+        UseCase::DiffEnvironment(_)
+        | UseCase::VerifyEnvironments(..)
+        | UseCase::UseEnvironment(..)
+        | UseCase::UseStage(..)
+        | UseCase::ShowStatus
+        | UseCase::SyncEnvironments(..)
+        | UseCase::GenerateTemplate(..)
+        | UseCase::ShowEnvironment(..)
+        | UseCase::ExecEnvironment(..)
+        | UseCase::ExplainVariable(..) => true,
+        _ => false,
+    }
+}
+
+// The line written by sync or template generation, with the value redacted like the others.
+fn redact_line(line: &str, redactor: &Redactor) -> String {
+    match dotenv::parse_line(line) {
+        Some((key, value)) => format!("{}={}", key, redactor.redact(&key, &value)),
+        None => line.to_string(),
+    }
+}
+
+// Verify the environment before using it, every environment of the selection is verified.
+fn use_environment(
+    repo: &Repository,
+    target: &str,
+    force: bool,
+    redactor: &Redactor,
+) -> Result<(), EnvmError> {
+    let envs = match EnvType::from(target) {
+        EnvType::Selection(_) => repo.select(target),
+        _ => vec![String::from(target)],
+    };
+    check_environments(repo, &envs, force, redactor)?;
+    repo.use_environment(target)?;
+    println!("switch to {} environment", target);
+    if matches!(EnvType::from(target), EnvType::Selection(_)) {
+        for env in &envs {
+            println!("  {}", env);
+        }
+    }
+    Ok(())
+}
+
+// Print the problems of the environments, and refuse the ones which cannot be used under their
+// strictness unless forced.
+fn check_environments(
+    repo: &Repository,
+    envs: &[String],
+    force: bool,
+    redactor: &Redactor,
+) -> Result<(), EnvmError> {
+    for env in envs {
+        if let Some(verification) = repo.check_before_use(env)? {
+            if envs.len() > 1 {
+                println!("'{}' environment:", env);
+            }
+            print_verification(&verification, redactor);
+            // The problems allowed under the strictness are only warned about.
+            if verification.is_acceptable(repo.strictness(env)) {
+                if verification.tampered {
+                    println!(
+                        "{}",
+                        "warning: the environment is modified outside envm".yellow()
+                    );
+                }
+            } else if !force {
+                return Err(EnvmError::RefuseToUseEnvironment(env.clone()));
+            } else {
+                println!("{}", "warning: force to use the environment".yellow());
+            }
+        }
+    }
+    Ok(())
+}
+
+// The rows are padded to the widest cell of each column.
+fn print_matrix(matrix: &Matrix) {
+    let header = format!("{} \\ {}", matrix.rows_dimension, matrix.columns_dimension);
+    let first_width = matrix
+        .rows
+        .iter()
+        .map(|(it, _)| it.len())
+        .chain([header.len()])
+        .max()
+        .unwrap_or_default();
+    let mut line = format!("{:width$}", header, width = first_width);
+    for column in &matrix.columns {
+        line.push_str(&format!("  {}", column));
+    }
+    println!("{}", line.bold());
+    for (row, exists) in &matrix.rows {
+        let mut line = format!("{:width$}", row, width = first_width);
+        for (column, exists) in matrix.columns.iter().zip(exists) {
+            let cell = if *exists { "x" } else { "-" };
+            line.push_str(&format!("  {:width$}", cell, width = column.len()));
+        }
+        println!("{}", line.trim_end());
+    }
+}
+
+// Use all environments if the user didn't specify any.
+fn target_environments(repo: &Repository, envs: Vec<String>) -> Vec<String> {
+    if envs.is_empty() {
+        repo.default_environments()
+    } else {
+        envs
+    }
+}
+
+fn print_verification(verification: &Verification, redactor: &Redactor) {
+    if !verification.missing.is_empty() {
+        println!("missing variables:");
+        verification
+            .missing
+            .iter()
+            .map(|it| format!("- {}", it).red())
+            .for_each(|it| println!("{}", it));
+    }
+    if !verification.extra.is_empty() {
+        println!("extra variables:");
+        verification
+            .extra
+            .iter()
+            .map(|it| format!("+ {}{}", it, source(verification, it)).green())
+            .for_each(|it| println!("{}", it));
+    }
+    if !verification.empty_required.is_empty() {
+        println!("empty required variables:");
+        verification
+            .empty_required
+            .iter()
+            .map(|it| format!("! {}{}", it, source(verification, it)).yellow())
+            .for_each(|it| println!("{}", it));
+    }
+    print_placeholders(verification);
+    print_signature(verification);
+    if !verification.invalid.is_empty() {
+        println!("invalid values:");
+        for it in &verification.invalid {
+            println!(
+                "{}:{}: {}: {}",
+                verification.source(&it.key),
+                it.line,
+                it.key.bold(),
+                redactor
+                    .redact_interpolated_text(
+                        &it.key,
+                        &it.value,
+                        verification.references(&it.key),
+                        &it.message
+                    )
+                    .red()
+            );
+        }
+    }
+}
+
+// The file where the variable comes from, which is only shown when the environment includes
+// other files.
+fn source(verification: &Verification, key: &str) -> String {
+    if verification.sources.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", verification.source(key))
+    }
+}
+
+fn print_signature(verification: &Verification) {
+    if verification.tampered {
+        println!(
+            "{}",
+            "signature mismatch: the file is modified outside envm, run `envm sign` after checking it"
+                .red()
+        );
+    } else if verification.unsigned {
+        println!("{}", "not signed: run `envm sign` to sign it".yellow());
+    }
+}
+
+fn print_placeholders(verification: &Verification) {
+    if !verification.placeholders.is_empty() {
+        println!("placeholder values:");
+        verification
+            .placeholders
+            .iter()
+            .map(|it| format!("? {}{}", it, source(verification, it)).yellow())
+            .for_each(|it| println!("{}", it));
+    }
+}
+
+// Run the agent in a detached process, and wait until it's listening.
+fn start_agent(socket: &Path, lifetime: u64) -> Result<(), EnvmError> {
+    let executable =
+        env::current_exe().map_err(|err| EnvmError::FailedToStartAgent(err.to_string()))?;
+    process::Command::new(executable)
+        .args(["agent", "start", "--foreground", "--lifetime"])
+        .arg(lifetime.to_string())
+        .arg("--socket")
+        .arg(socket)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| EnvmError::FailedToStartAgent(err.to_string()))?;
+    let started = Instant::now();
+    while !socket.exists() {
+        if started.elapsed() > Duration::from_secs(5) {
+            return Err(EnvmError::FailedToStartAgent(String::from(
+                "timed out waiting for the socket",
+            )));
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+// Printed in the shell syntax so it can be used as `eval "$(envm agent start)"`.
+fn print_agent_socket(socket: &Path) {
+    println!(
+        "{}={}; export {};",
+        agent::SOCKET_VARIABLE,
+        socket.display(),
+        agent::SOCKET_VARIABLE
+    );
+}
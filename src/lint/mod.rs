@@ -0,0 +1,365 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::configuration::parser::dotenv;
+
+pub mod secrets;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Off,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rule {
+    DuplicateKey,
+    InvalidKey,
+    LowercaseKey,
+    SpaceAroundEquals,
+    UnquotedValue,
+    UnbalancedQuotes,
+    TrailingWhitespace,
+    CrlfLineEnding,
+    MissingFinalNewline,
+}
+
+impl Rule {
+    pub fn name(&self) -> &str {
+        match self {
+            Rule::DuplicateKey => "duplicate-key",
+            Rule::InvalidKey => "invalid-key",
+            Rule::LowercaseKey => "lowercase-key",
+            Rule::SpaceAroundEquals => "space-around-equals",
+            Rule::UnquotedValue => "unquoted-value",
+            Rule::UnbalancedQuotes => "unbalanced-quotes",
+            Rule::TrailingWhitespace => "trailing-whitespace",
+            Rule::CrlfLineEnding => "crlf-line-ending",
+            Rule::MissingFinalNewline => "missing-final-newline",
+        }
+    }
+
+    // The rules which break the loaders are errors, the others are about the style.
+    fn default_severity(&self) -> Severity {
+        match self {
+            Rule::DuplicateKey | Rule::InvalidKey | Rule::UnbalancedQuotes => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+// The lint settings in the config, for example:
+//
+//   [lint]
+//   allow = ["npm_config_cache"]
+//
+//   [lint.severity]
+//   lowercase-key = "off"
+//   unquoted-value = "error"
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct LintConfig {
+    // The keys which are not checked by the rules about the key and value.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub severity: HashMap<String, Severity>,
+}
+
+impl LintConfig {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.severity.is_empty()
+    }
+
+    pub fn severity(&self, rule: Rule) -> Severity {
+        self.severity
+            .get(rule.name())
+            .cloned()
+            .unwrap_or_else(|| rule.default_severity())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub line: usize,
+    pub rule: Rule,
+    pub severity: Severity,
+    pub message: String,
+}
+
+pub fn lint(content: &str, config: &LintConfig) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut report = |line: usize, rule: Rule, message: String| {
+        let severity = config.severity(rule);
+        if severity != Severity::Off {
+            problems.push(Problem {
+                line,
+                rule,
+                severity,
+                message,
+            });
+        }
+    };
+    let key_re = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    let mut defined: HashMap<String, usize> = HashMap::new();
+    let mut crlf_reported = false;
+    let lines: Vec<&str> = content.split('\n').collect();
+    for (index, raw) in lines.iter().enumerate() {
+        let line = index + 1;
+        if index == lines.len() - 1 && raw.is_empty() {
+            break;
+        }
+        let text = raw.strip_suffix('\r').unwrap_or(raw);
+        if text.len() != raw.len() && !crlf_reported {
+            // Only report the line ending once, since the whole file usually uses the same one.
+            crlf_reported = true;
+            report(
+                line,
+                Rule::CrlfLineEnding,
+                String::from("line ends with CRLF"),
+            );
+        }
+        if text.ends_with(|it: char| it.is_whitespace()) {
+            report(
+                line,
+                Rule::TrailingWhitespace,
+                String::from("trailing whitespace"),
+            );
+        }
+        let (key, value) = match dotenv::parse_line(text) {
+            Some(pair) => pair,
+            None => continue,
+        };
+        if config.allow.contains(&key) {
+            continue;
+        }
+        if let Some(first) = defined.get(&key) {
+            report(
+                line,
+                Rule::DuplicateKey,
+                format!("{} is already defined at line {}", key, first),
+            );
+        } else {
+            defined.insert(key.clone(), line);
+        }
+        if !key_re.is_match(&key) {
+            report(
+                line,
+                Rule::InvalidKey,
+                format!("{} is not a valid key", key),
+            );
+        } else if key.chars().any(|it| it.is_lowercase()) {
+            report(
+                line,
+                Rule::LowercaseKey,
+                format!("{} should be uppercase", key),
+            );
+        }
+        let (raw_key, raw_value) = text.trim_start().split_once('=').unwrap();
+        if raw_key.ends_with(char::is_whitespace) || raw_value.starts_with(char::is_whitespace) {
+            report(
+                line,
+                Rule::SpaceAroundEquals,
+                String::from("spaces around `=`"),
+            );
+        }
+        if !is_balanced(&value) {
+            report(
+                line,
+                Rule::UnbalancedQuotes,
+                String::from("unbalanced quotes"),
+            );
+        } else if !is_quoted(&value) && value.contains(|it: char| it.is_whitespace() || it == '#') {
+            report(
+                line,
+                Rule::UnquotedValue,
+                String::from("value with spaces or `#` should be quoted"),
+            );
+        }
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        report(
+            lines.len(),
+            Rule::MissingFinalNewline,
+            String::from("missing final newline"),
+        );
+    }
+    problems
+}
+
+// Fix the problems which can be fixed without changing the meaning of the file.
+pub fn fix(content: &str, config: &LintConfig) -> String {
+    let enabled = |rule: Rule| config.severity(rule) != Severity::Off;
+    let mut lines: Vec<String> = content
+        .split('\n')
+        .map(|raw| {
+            let mut text = raw.to_string();
+            if enabled(Rule::CrlfLineEnding) && text.ends_with('\r') {
+                text.pop();
+            }
+            if enabled(Rule::TrailingWhitespace) {
+                text = text.trim_end().to_string();
+            }
+            let (key, value) = match dotenv::parse_line(&text) {
+                Some(pair) if !config.allow.contains(&pair.0) => pair,
+                _ => return text,
+            };
+            let (raw_key, raw_value) = text.trim_start().split_once('=').unwrap();
+            let mut value_changed = false;
+            let value = if enabled(Rule::UnquotedValue)
+                && is_balanced(&value)
+                && !is_quoted(&value)
+                && value.contains(char::is_whitespace)
+                && !value.contains('#')
+            {
+                value_changed = true;
+                quote(&value)
+            } else {
+                value
+            };
+            let has_space = raw_key.ends_with(char::is_whitespace)
+                || raw_value.starts_with(char::is_whitespace);
+            let indent = &text[..text.len() - text.trim_start().len()];
+            if enabled(Rule::SpaceAroundEquals) && (has_space || value_changed) {
+                format!("{}{}={}", indent, key, value)
+            } else if value_changed {
+                let spacing = &raw_value[..raw_value.len() - raw_value.trim_start().len()];
+                format!("{}{}={}{}", indent, raw_key, spacing, value)
+            } else {
+                text
+            }
+        })
+        .collect();
+    let has_final_newline = content.ends_with('\n');
+    if has_final_newline {
+        lines.pop();
+    }
+    let line_ending = if !enabled(Rule::CrlfLineEnding) && content.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut fixed = lines.join(line_ending);
+    if !fixed.is_empty() && (has_final_newline || enabled(Rule::MissingFinalNewline)) {
+        fixed.push_str(line_ending);
+    }
+    fixed
+}
+
+pub fn is_quoted(value: &str) -> bool {
+    value.starts_with('"') || value.starts_with('\'')
+}
+
+// The quoted value should end with the same quote, and the unquoted value should not end with
+// a quote, the comment after the closing quote is allowed.
+pub fn is_balanced(value: &str) -> bool {
+    let quote = match value.chars().next() {
+        Some(it @ ('"' | '\'')) => it,
+        _ => return !value.ends_with('"') && !value.ends_with('\''),
+    };
+    let mut escaped = false;
+    for (index, it) in value.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if it == '\\' && quote == '"' {
+            escaped = true;
+        } else if it == quote {
+            let rest = value[index + 1..].trim();
+            return rest.is_empty() || rest.starts_with('#');
+        }
+    }
+    false
+}
+
+// Wrap the value in double quotes, the characters which have special meaning are escaped.
+pub fn quote(value: &str) -> String {
+    format!(
+        "\"{}\"",
+        value
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(problems: &[Problem]) -> Vec<(usize, &str)> {
+        problems
+            .iter()
+            .map(|it| (it.line, it.rule.name()))
+            .collect()
+    }
+
+    #[test]
+    fn should_report_problems() {
+        let content = "A=1\r\nA=2 \r\nlower=1\r\n1KEY=x\r\nB = 2\r\nC=hello world\r\nD=\"open\r\nE=\"ok\" # comment";
+        let problems = lint(content, &LintConfig::default());
+
+        assert_eq!(
+            rules(&problems),
+            vec![
+                (1, "crlf-line-ending"),
+                (2, "trailing-whitespace"),
+                (2, "duplicate-key"),
+                (3, "lowercase-key"),
+                (4, "invalid-key"),
+                (5, "space-around-equals"),
+                (6, "unquoted-value"),
+                (7, "unbalanced-quotes"),
+                (8, "missing-final-newline"),
+            ]
+        );
+        assert_eq!(problems[2].severity, Severity::Error);
+        assert_eq!(problems[3].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn should_respect_config() {
+        let mut config = LintConfig::default();
+        config.allow.push(String::from("lower"));
+        config
+            .severity
+            .insert(String::from("missing-final-newline"), Severity::Off);
+        config
+            .severity
+            .insert(String::from("space-around-equals"), Severity::Error);
+
+        let problems = lint("lower=1\nother=1\nB = 2", &config);
+
+        assert_eq!(
+            rules(&problems),
+            vec![(2, "lowercase-key"), (3, "space-around-equals")]
+        );
+        assert_eq!(problems[1].severity, Severity::Error);
+    }
+
+    #[test]
+    fn should_fix_problems() {
+        let content = "# comment  \r\nA = 1\r\nB=hello world\r\nC=a # b\r\nD=\"x\"";
+        let fixed = fix(content, &LintConfig::default());
+
+        assert_eq!(
+            fixed,
+            "# comment\nA=1\nB=\"hello world\"\nC=a # b\nD=\"x\"\n"
+        );
+        assert_eq!(
+            rules(&lint(&fixed, &LintConfig::default())),
+            vec![(4, "unquoted-value")]
+        );
+    }
+
+    #[test]
+    fn should_check_quotes() {
+        assert!(is_balanced("\"a \\\" b\""));
+        assert!(is_balanced("'a' # comment"));
+        assert!(is_balanced("plain"));
+        assert!(!is_balanced("\"open"));
+        assert!(!is_balanced("close'"));
+        assert!(!is_balanced("\"a\" b"));
+    }
+}
@@ -3,5 +3,6 @@ use envm;
 fn main() {
     if let Err(err) = envm::run() {
         eprintln!("{}", err);
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,392 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+use crate::configuration::parser::dotenv;
+use crate::format;
+
+// The schemes of the references, a value like `@cmd:pass show db` is a reference only when the
+// scheme is one of them, so the other values starting with `@` are kept as they are.
+const SCHEMES: &[&str] = &["cmd", "file"];
+
+// The config is shared like the environment files, so whoever can change them could enable
+// `@cmd:` as well. Each user opts in to run the commands with `ENVM_ALLOW_COMMANDS=1`, and to
+// read the files with `ENVM_ALLOW_FILES=1`, since `@file:` can point at any file of the user.
+pub const ALLOW_COMMANDS_VARIABLE: &str = "ENVM_ALLOW_COMMANDS";
+pub const ALLOW_FILES_VARIABLE: &str = "ENVM_ALLOW_FILES";
+
+// The references the user allows, on top of what the config enables.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Permissions {
+    pub commands: bool,
+    pub files: bool,
+}
+
+impl Permissions {
+    pub fn from_env() -> Permissions {
+        Permissions {
+            commands: is_allowed(ALLOW_COMMANDS_VARIABLE),
+            files: is_allowed(ALLOW_FILES_VARIABLE),
+        }
+    }
+}
+
+fn is_allowed(variable: &str) -> bool {
+    env::var(variable).is_ok_and(|it| it == "1" || it == "true")
+}
+
+// The settings of the secret references in the config, for example:
+//
+//   [references]
+//   timeout = 5
+//   commands = true
+//   files = true
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct ReferenceConfig {
+    // The seconds to wait for each command.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    // `@cmd:` runs the command written in the environment file, so it's only allowed when enabled
+    // here and by the user.
+    #[serde(default)]
+    pub commands: bool,
+    // `@file:` reads the file at the path written in the environment file, so it's gated the
+    // same way.
+    #[serde(default)]
+    pub files: bool,
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+impl Default for ReferenceConfig {
+    fn default() -> ReferenceConfig {
+        ReferenceConfig {
+            timeout: default_timeout(),
+            commands: false,
+            files: false,
+        }
+    }
+}
+
+impl ReferenceConfig {
+    pub fn is_default(&self) -> bool {
+        *self == ReferenceConfig::default()
+    }
+}
+
+// Resolve the reference of a scheme to the value.
+pub trait Provider {
+    fn scheme(&self) -> &'static str;
+    fn resolve(&self, reference: &str) -> Result<String, String>;
+}
+
+// `@file:/run/secrets/db` reads the value from the file, like the secrets mounted by Docker.
+pub struct FileProvider;
+
+impl Provider for FileProvider {
+    fn scheme(&self) -> &'static str {
+        "file"
+    }
+
+    fn resolve(&self, reference: &str) -> Result<String, String> {
+        fs::read_to_string(reference)
+            .map(|it| trim_newline(&it))
+            .map_err(|err| format!("cannot read {}: {}", reference, err))
+    }
+}
+
+// `@cmd:pass show db/prod` runs the command with `sh` and takes its output, the command is
+// killed if it doesn't finish in time.
+pub struct CommandProvider {
+    pub timeout: Duration,
+}
+
+impl Provider for CommandProvider {
+    fn scheme(&self) -> &'static str {
+        "cmd"
+    }
+
+    fn resolve(&self, reference: &str) -> Result<String, String> {
+        // The stdin and stderr are kept, so the command can ask for the password of its store.
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(reference)
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("cannot run `{}`: {}", reference, err))?;
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait().map_err(|err| err.to_string())? {
+                break status;
+            }
+            if started.elapsed() > self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!(
+                    "`{}` timed out after {}s",
+                    reference,
+                    self.timeout.as_secs_f32()
+                ));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        if !status.success() {
+            return Err(format!("`{}` failed with {}", reference, status));
+        }
+        let output = reader
+            .join()
+            .unwrap()
+            .map_err(|err| format!("cannot read the output of `{}`: {}", reference, err))?;
+        Ok(trim_newline(&output))
+    }
+}
+
+// Resolve the references through the providers, the same reference is resolved only once.
+pub struct Resolver {
+    providers: Vec<Box<dyn Provider>>,
+    // The schemes enabled in config, to tell why a scheme has no provider.
+    commands: bool,
+    files: bool,
+    cache: HashMap<String, Result<String, String>>,
+}
+
+impl Resolver {
+    pub fn new(config: &ReferenceConfig, permissions: Permissions) -> Resolver {
+        let mut providers: Vec<Box<dyn Provider>> = vec![];
+        if config.files && permissions.files {
+            providers.push(Box::new(FileProvider));
+        }
+        if config.commands && permissions.commands {
+            providers.push(Box::new(CommandProvider {
+                timeout: Duration::from_secs(config.timeout),
+            }));
+        }
+        Resolver::with_providers(providers, config)
+    }
+
+    pub fn with_providers(providers: Vec<Box<dyn Provider>>, config: &ReferenceConfig) -> Resolver {
+        Resolver {
+            providers,
+            commands: config.commands,
+            files: config.files,
+            cache: HashMap::new(),
+        }
+    }
+
+    // Return None if the value is not a reference.
+    pub fn resolve(&mut self, value: &str) -> Option<Result<String, String>> {
+        let (scheme, reference) = parse(value)?;
+        if let Some(resolved) = self.cache.get(value) {
+            return Some(resolved.clone());
+        }
+        let resolved = match self.providers.iter().find(|it| it.scheme() == scheme) {
+            Some(provider) => provider.resolve(reference),
+            None if scheme == "cmd" && !self.commands => Err(String::from(
+                "`@cmd:` references are disabled, set `commands = true` under `[references]` in config",
+            )),
+            None if scheme == "cmd" => Err(format!(
+                "`@cmd:` references are not allowed by the user, set {}=1 to run them",
+                ALLOW_COMMANDS_VARIABLE
+            )),
+            None if scheme == "file" && !self.files => Err(String::from(
+                "`@file:` references are disabled, set `files = true` under `[references]` in config",
+            )),
+            None if scheme == "file" => Err(format!(
+                "`@file:` references are not allowed by the user, set {}=1 to read them",
+                ALLOW_FILES_VARIABLE
+            )),
+            None => Err(format!("no provider for `@{}:`", scheme)),
+        };
+        self.cache.insert(value.to_string(), resolved.clone());
+        Some(resolved)
+    }
+
+    // Replace the references in the content with the resolved values, all references are
+    // resolved before returning the errors so they can be fixed at once.
+    pub fn resolve_content(&mut self, content: &str) -> Result<String, Vec<(String, String)>> {
+        let mut errors = vec![];
+        let mut lines = vec![];
+        for line in content.lines() {
+            let resolved = dotenv::parse_line(line)
+                .and_then(|(key, value)| self.resolve(&value).map(|it| (key, it)));
+            match resolved {
+                Some((key, Ok(value))) => {
+                    lines.push(format!("{}={}", key, format::quote_if_needed(&value)))
+                }
+                Some((key, Err(err))) => errors.push((key, err)),
+                None => lines.push(line.to_string()),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let line_ending = if content.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+        let mut resolved = lines.join(line_ending);
+        if content.ends_with('\n') {
+            resolved.push_str(line_ending);
+        }
+        Ok(resolved)
+    }
+}
+
+pub fn parse(value: &str) -> Option<(&str, &str)> {
+    let (scheme, reference) = value.strip_prefix('@')?.split_once(':')?;
+    if SCHEMES.contains(&scheme) && !reference.trim().is_empty() {
+        Some((scheme, reference.trim()))
+    } else {
+        None
+    }
+}
+
+pub fn is_reference(value: &str) -> bool {
+    parse(value).is_some()
+}
+
+// The output usually ends with a newline which is not part of the value.
+fn trim_newline(output: &str) -> String {
+    output
+        .strip_suffix('\n')
+        .map(|it| it.strip_suffix('\r').unwrap_or(it))
+        .unwrap_or(output)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::error::Error;
+    use std::rc::Rc;
+    use tempfile::NamedTempFile;
+
+    struct CountingProvider {
+        count: Rc<Cell<usize>>,
+    }
+
+    impl Provider for CountingProvider {
+        fn scheme(&self) -> &'static str {
+            "cmd"
+        }
+
+        fn resolve(&self, reference: &str) -> Result<String, String> {
+            self.count.set(self.count.get() + 1);
+            Ok(reference.to_uppercase())
+        }
+    }
+
+    #[test]
+    fn should_parse_references() {
+        assert_eq!(
+            parse("@cmd:pass show db/prod"),
+            Some(("cmd", "pass show db/prod"))
+        );
+        assert_eq!(
+            parse("@file:/run/secrets/db"),
+            Some(("file", "/run/secrets/db"))
+        );
+        assert_eq!(parse("@user:name"), None);
+        assert_eq!(parse("@cmd:"), None);
+        assert_eq!(parse("cmd:echo"), None);
+    }
+
+    #[test]
+    fn should_resolve_content_with_cache() {
+        let count = Rc::new(Cell::new(0));
+        let provider = CountingProvider {
+            count: Rc::clone(&count),
+        };
+        let config = ReferenceConfig {
+            commands: true,
+            ..ReferenceConfig::default()
+        };
+        let mut resolver = Resolver::with_providers(vec![Box::new(provider)], &config);
+
+        let resolved = resolver.resolve_content("# db\nA=@cmd:secret\nB=@cmd:secret\nC=@me:x\n");
+
+        assert_eq!(
+            resolved,
+            Ok(String::from("# db\nA=SECRET\nB=SECRET\nC=@me:x\n"))
+        );
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn should_report_each_failed_reference() {
+        let mut resolver = Resolver::new(&ReferenceConfig::default(), Permissions::default());
+
+        let errors = resolver
+            .resolve_content("A=@file:/missing/a\nB=@cmd:echo b\nC=1\n")
+            .unwrap_err();
+
+        let keys: Vec<&str> = errors.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, vec!["A", "B"]);
+        assert!(errors[0].1.contains("disabled"));
+        assert!(errors[1].1.contains("disabled"));
+        let config = ReferenceConfig {
+            commands: true,
+            files: true,
+            ..ReferenceConfig::default()
+        };
+        let mut resolver = Resolver::new(&config, Permissions::default());
+        let err = resolver.resolve("@cmd:echo b").unwrap().unwrap_err();
+        assert!(err.contains(ALLOW_COMMANDS_VARIABLE));
+        let err = resolver
+            .resolve("@file:/etc/hostname")
+            .unwrap()
+            .unwrap_err();
+        assert!(err.contains(ALLOW_FILES_VARIABLE));
+    }
+
+    #[test]
+    fn should_resolve_files_and_commands() -> Result<(), Box<dyn Error>> {
+        let file = NamedTempFile::new()?;
+        fs::write(file.path(), "s3cret\n")?;
+        let config = ReferenceConfig {
+            timeout: 5,
+            commands: true,
+            files: true,
+        };
+        let permissions = Permissions {
+            commands: true,
+            files: true,
+        };
+        let mut resolver = Resolver::new(&config, permissions);
+
+        let content = format!(
+            "A=@file:{}\nB=@cmd:echo 'hello world'\n",
+            file.path().display()
+        );
+        assert_eq!(
+            resolver.resolve_content(&content),
+            Ok(String::from("A=s3cret\nB=\"hello world\"\n"))
+        );
+        assert!(resolver.resolve("@cmd:exit 3").unwrap().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_kill_slow_commands() {
+        let provider = CommandProvider {
+            timeout: Duration::from_millis(100),
+        };
+        let started = Instant::now();
+        let err = provider.resolve("sleep 5").unwrap_err();
+        assert!(err.contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,23 +1,55 @@
 use regex::Regex;
 use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap},
+    env,
     ffi::OsStr,
     fs,
+    io::Write,
     path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 pub mod config;
 pub mod environment;
+pub mod include;
 pub mod path;
+pub mod search;
+pub mod verification;
 
-use crate::configuration;
+use crate::agent::{self, Client};
+use crate::configuration::document::Document;
+use crate::configuration::interpolation::{self, References};
+use crate::configuration::json_schema;
+use crate::configuration::parser::dotenv::{self, Entry};
+use crate::configuration::schema::Schema;
+use crate::configuration::validation::{self, Issue};
+use crate::configuration::{self, Configuration};
+use crate::crypto::{self, values};
 use crate::error::EnvmError;
-use crate::repository::config::Config;
-use crate::repository::environment::EnvType;
+use crate::fingerprint::Fingerprint;
+use crate::format;
+use crate::lint::secrets::{self, Finding};
+use crate::lint::{self, Problem};
+use crate::redaction::Redactor;
+use crate::reference::{self, Permissions, Resolver};
+use crate::repository::config::{Config, Strictness};
+use crate::repository::environment::{EnvType, Matrix};
+use crate::repository::include::{self as includes, Composition, Definition};
+use crate::repository::search::{SearchMatch, SearchScope};
+use crate::repository::verification::Verification;
+use crate::signature::{self, Manifest, Status};
 
 pub struct Repository {
     path: PathBuf,
     config: Config,
     current_env: EnvType,
+    // The passphrase and the identity of the encrypted environments, only read once per run.
+    passphrase: RefCell<Option<String>>,
+    identity: RefCell<Option<String>>,
+    signing_key: RefCell<Option<Vec<u8>>>,
+    // The references the user allows to resolve, read from the process environment.
+    permissions: Permissions,
 }
 
 impl Repository {
@@ -26,6 +58,10 @@ impl Repository {
             path,
             config: Config::new(),
             current_env: EnvType::Local,
+            passphrase: RefCell::new(None),
+            identity: RefCell::new(None),
+            signing_key: RefCell::new(None),
+            permissions: Permissions::from_env(),
         }
     }
 
@@ -35,7 +71,7 @@ impl Repository {
             None => return Err(EnvmError::NotEnvmRepository),
         };
         let config_path = path::get_config_path(&path);
-        let config = Config::load(&config_path.to_str().unwrap())?;
+        let config = Config::load(config_path.to_str().unwrap())?;
         let head_path = path::get_current_path(&path);
         let contents = fs::read_to_string(head_path).map_err(|_| EnvmError::MissngHeadFile)?;
         let current_env = EnvType::from(&contents);
@@ -43,6 +79,10 @@ impl Repository {
             path,
             config,
             current_env,
+            passphrase: RefCell::new(None),
+            identity: RefCell::new(None),
+            signing_key: RefCell::new(None),
+            permissions: Permissions::from_env(),
         })
     }
 
@@ -50,6 +90,16 @@ impl Repository {
         &self.config
     }
 
+    // The strictest one of the stacked environments.
+    pub fn strictness(&self, env: &str) -> Strictness {
+        EnvType::from(env)
+            .layers()
+            .iter()
+            .map(|it| self.config.strictness(it))
+            .max()
+            .unwrap_or_default()
+    }
+
     fn set_head(&self, env: &str) {
         let env = EnvType::from(env);
         let head_path = path::get_current_path(&self.path);
@@ -57,12 +107,16 @@ impl Repository {
     }
 
     pub fn use_environment(&self, env: &str) -> Result<(), EnvmError> {
-        if env == self.current_env.to_string() {
+        self.check_environment_name(env)?;
+        let env_type = EnvType::from(env);
+        if self.current_env.is_equal(&env_type) {
             return Err(EnvmError::AlreadyUsingTargetEnvironment(String::from(env)));
         }
         let local_env_path = path::get_local_env_path(self);
         let backup_path = path::get_local_backup_path(self);
-        if matches!(self.current_env, EnvType::Local) {
+        // The local with the placeholders is a file per service, which is not backup.
+        let has_local_dimensions = !self.config.local_dimensions().is_empty();
+        if matches!(self.current_env, EnvType::Local) && !has_local_dimensions {
             fs::copy(&local_env_path, &backup_path)
                 .map_err(|_| EnvmError::FailedToBackupLocalEnvironment)?;
         }
@@ -75,22 +129,103 @@ impl Repository {
                 Err(err)
             }
         };
-        match EnvType::from(env) {
+        match env_type {
             EnvType::Local => {
                 copy(&backup_path, EnvmError::MissingBackupEnvironment)?;
             }
-            EnvType::Other(_) => {
-                let target_env = path::get_env_path(self, env);
-                copy(
-                    &target_env,
-                    EnvmError::MissingTargetEnvironment(String::from(env)),
-                )?;
+            EnvType::Other(_) | EnvType::Stack(_) => self.materialize(env)?,
+            EnvType::Selection(_) => {
+                let envs = self.select(env);
+                if envs.is_empty() {
+                    return Err(EnvmError::MissingTargetEnvironment(String::from(env)));
+                }
+                let local_paths: BTreeSet<PathBuf> = envs
+                    .iter()
+                    .map(|it| path::get_local_env_path_of(self, it))
+                    .collect();
+                if local_paths.len() < envs.len() {
+                    return Err(EnvmError::SharedLocalEnvironment(String::from(env)));
+                }
+                for it in envs {
+                    self.materialize(&it)?;
+                }
             }
         }
         self.set_head(env);
         Ok(())
     }
 
+    // Write the environment to its local file.
+    fn materialize(&self, env: &str) -> Result<(), EnvmError> {
+        // Decrypt to the local file directly, the plaintext is never written elsewhere. The
+        // references are resolved here as well, so the values only exist in the local file. The
+        // file is kept as it is unless it includes, inherits or stacks other files.
+        let env_type = EnvType::from(env);
+        let layers = env_type.layers();
+        let composition = self.compose_environment(env)?;
+        let content = if composition.is_composed() {
+            composition.content()
+        } else {
+            self.read_environment(layers[0])?
+        };
+        let content = Resolver::new(self.config.references(), self.permissions)
+            .resolve_content(&content)
+            .map_err(EnvmError::FailedToResolveReferences)?;
+        let local_env_path = path::get_local_env_path_of(self, layers.last().unwrap());
+        if let Some(dir) = local_env_path.parent() {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(local_env_path, content).unwrap();
+        Ok(())
+    }
+
+    // Every environment, or every layer of the stack, has a value for each named placeholder. The
+    // selection is checked by matching the listed environments.
+    fn check_environment_name(&self, env: &str) -> Result<(), EnvmError> {
+        let env_type = EnvType::from(env);
+        if matches!(env_type, EnvType::Local | EnvType::Selection(_)) {
+            return Ok(());
+        }
+        for layer in env_type.layers() {
+            if !path::has_all_dimensions(self, layer) {
+                return Err(EnvmError::InvalidEnvironmentName(
+                    String::from(layer),
+                    self.config.dimensions().join("."),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // The environments matching the selection like `*.staging`.
+    pub fn select(&self, selection: &str) -> Vec<String> {
+        let selection = EnvType::from(selection);
+        let mut envs: Vec<String> = self
+            .list_environments()
+            .into_iter()
+            .filter(|it| selection.is_using(it))
+            .collect();
+        envs.sort();
+        envs
+    }
+
+    // The selection of the stage in every service, like `*.staging` of `.env.{service}.{stage}`.
+    pub fn stage_selection(&self, stage: &str) -> Result<String, EnvmError> {
+        let dimensions = self.config.dimensions();
+        if !dimensions.iter().any(|it| it == "stage") {
+            return Err(EnvmError::MissingDimension(String::from("stage")));
+        }
+        Ok(dimensions
+            .iter()
+            .map(|it| if it == "stage" { stage } else { "*" })
+            .collect::<Vec<&str>>()
+            .join("."))
+    }
+
+    pub fn environment_matrix(&self) -> Matrix {
+        Matrix::new(&self.config.dimensions(), &self.list_environments())
+    }
+
     pub fn init(&self) -> Result<PathBuf, EnvmError> {
         let envm_path = path::get_envm_path(&self.path);
         if envm_path.exists() {
@@ -108,31 +243,63 @@ impl Repository {
     }
 
     pub fn new_environment(&self, env: &str) -> Result<(), EnvmError> {
+        if matches!(EnvType::from(env), EnvType::Stack(_)) {
+            return Err(EnvmError::InvalidEnvironmentStack(
+                String::from(env),
+                String::from("cannot create a stack, `+` separates the stacked environments"),
+            ));
+        }
+        self.check_environment_name(env)?;
         let template_path = path::get_template_env_path(self);
-        let target_path = match EnvType::from(env) {
-            EnvType::Local => path::get_local_env_path(self),
-            EnvType::Other(_) => path::get_env_path(self, env),
-        };
+        let target_path = path::get_env_or_local_path(self, env);
         if !template_path.exists() {
             return Err(EnvmError::MissingTemplateEnvironment(
                 self.config.template().clone(),
             ));
         }
-        if target_path.exists() {
+        if self.environment_exists(env) {
             return Err(EnvmError::TargetEnvironmentAlreadyExists(String::from(env)));
         }
-        fs::copy(template_path, target_path).unwrap();
-        Ok(())
+        let mut template = Document::parse(&fs::read_to_string(template_path).unwrap());
+        let schema = self.schema()?;
+        let values = configuration::parse_document(&template);
+        for (key, field) in schema.fields() {
+            if let (Some(default), Some(value)) = (&field.default, values.get(key)) {
+                if dotenv::unquote(value).is_empty() {
+                    template.set(key, default);
+                }
+            }
+        }
+        fs::write(target_path, template.to_string()).unwrap();
+        self.reseal(env)
+    }
+
+    // The environments to sync, prune or verify when none is named, the overlays are left out
+    // since they don't have every variable of the template.
+    pub fn default_environments(&self) -> Vec<String> {
+        let mut envs: Vec<String> = self
+            .list_environments()
+            .into_iter()
+            .filter(|it| !self.config.is_overlay(it))
+            .collect();
+        envs.sort();
+        envs
     }
 
     pub fn list_environments(&self) -> Vec<String> {
         let paths = fs::read_dir(&self.path).unwrap();
         let pattern = self.config.pattern();
-        let payload = format!(
-            "^{}$",
-            pattern.replace(".", r"\.").replace("{}", "(?P<env>.*?)")
-        );
-        let re = Regex::new(&payload).unwrap();
+        let dimensions = self.config.dimensions();
+        // The values of the named placeholders cannot have `.`, so the environment can be split
+        // back to them.
+        let mut payload = pattern.replace(".", r"\.").replace("{}", "(?P<env>.*?)");
+        for dimension in &dimensions {
+            payload = payload.replace(
+                &format!("{{{}}}", dimension),
+                &format!("(?P<{}>[^.]+)", dimension),
+            );
+        }
+        let re = Regex::new(&format!(r"^{}(?:\.enc)?$", payload)).unwrap();
         let template = OsStr::new(self.config.template());
 
         paths
@@ -144,217 +311,1854 @@ impl Repository {
             // NOTE: the file_name return a reference, since we cannot pass down the reference, so
             // we need to unwrap the filename twice.
             .filter(|it| it.file_name().unwrap() != template)
-            .map(|it| {
+            .filter_map(|it| {
                 let filename = it.file_name().unwrap().to_str().unwrap();
-                re.captures(filename).map(|caps| String::from(&caps["env"]))
+                re.captures(filename).map(|caps| {
+                    if dimensions.is_empty() {
+                        String::from(&caps["env"])
+                    } else {
+                        dimensions
+                            .iter()
+                            .map(|it| &caps[it.as_str()])
+                            .collect::<Vec<&str>>()
+                            .join(".")
+                    }
+                })
             })
-            .filter_map(|it| it)
+            .collect::<BTreeSet<String>>()
+            .into_iter()
             .collect()
     }
 
     pub fn remove_environment(&self, env: &str) -> Result<(), EnvmError> {
+        self.check_environment_name(env)?;
         let env_type = EnvType::from(env);
-        if self.current_env.is_equal(&env_type) {
+        if self.current_env.is_using(env) {
             return Err(EnvmError::RemovingUsingEnvironment(String::from(env)));
         }
-        let env_path = match env_type {
-            EnvType::Local => path::get_local_env_path(self),
-            EnvType::Other(env) => path::get_env_path(self, &env),
-        };
-        if !env_path.exists() {
+        if !self.environment_exists(env) {
             return Err(EnvmError::MissingTargetEnvironment(String::from(env)));
         }
-        fs::remove_file(env_path).unwrap();
-        Ok(())
+        let env_path = path::get_env_or_local_path(self, env);
+        if env_path.exists() {
+            fs::remove_file(env_path).unwrap();
+        }
+        if let EnvType::Other(env) = env_type {
+            let encrypted_path = path::get_encrypted_env_path(self, &env);
+            if encrypted_path.exists() {
+                fs::remove_file(encrypted_path).unwrap();
+            }
+        }
+        self.reseal(env)
     }
 
-    pub fn compare_to_template(&self, env: &String) -> (Option<Vec<String>>, Option<Vec<String>>) {
-        // detect configuration format
-        //   support format
-        //     - json
-        //     - toml
-        //     - yaml
-        //     - dotenv
-        // read template as hash map
-        // read target configuration as hash map
-        // compare two hash map
-        let template_configuration = configuration::parse(&path::get_template_env_path(self));
-        let target_configuration = configuration::parse(&path::get_env_path(self, env));
-        configuration::compare(&template_configuration, &target_configuration)
+    pub fn environment_exists(&self, env: &str) -> bool {
+        path::get_env_or_local_path(self, env).exists() || self.is_encrypted(env)
     }
 
-    pub fn current_env(&self) -> &EnvType {
-        &self.current_env
+    // The local environment is never encrypted since it's the file read by the application.
+    pub fn is_encrypted(&self, env: &str) -> bool {
+        match EnvType::from(env) {
+            EnvType::Local => false,
+            EnvType::Other(_) | EnvType::Stack(_) | EnvType::Selection(_) => {
+                path::get_encrypted_env_path(self, env).exists()
+            }
+        }
     }
-}
-
-fn lookup_repository(dir: PathBuf) -> Option<PathBuf> {
-    dir.ancestors()
-        .take_while(|it| path::is_envm_repository(it))
-        .next()
-        .map(|it| it.to_path_buf())
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::error::Error;
-    use tempfile;
 
-    #[test]
-    fn not_a_repository() {
-        let root = Path::new("/not/a/repository").to_path_buf();
-        assert_eq!(lookup_repository(root), None);
+    // The file storing the environment, which is the encrypted one if it exists.
+    fn environment_path(&self, env: &str) -> PathBuf {
+        if self.is_encrypted(env) {
+            path::get_encrypted_env_path(self, env)
+        } else {
+            path::get_env_or_local_path(self, env)
+        }
     }
 
-    #[test]
-    fn should_init_repo() -> Result<(), Box<dyn Error>> {
-        let dir = tempfile::tempdir()?;
-        let dir = dir.into_path();
-        let repo = Repository::new(dir.clone());
-        repo.init()?;
-        assert!(path::get_envm_path(&repo.path).exists());
-        assert!(path::get_config_path(&repo.path).exists());
-        assert!(path::get_current_path(&repo.path).exists());
-        fs::remove_dir_all(&dir)?;
-        Ok(())
+    // Whether each value of the environment is encrypted, while the keys stay in plaintext.
+    pub fn has_encrypted_values(&self, env: &str) -> bool {
+        !self.is_encrypted(env)
+            && fs::read_to_string(path::get_env_or_local_path(self, env))
+                .map(|it| values::has_encrypted_values(&it))
+                .unwrap_or(false)
     }
 
-    fn create_envm_repo_use_local_env() -> Result<Repository, Box<dyn Error>> {
-        let dir = tempfile::tempdir()?;
-        let dir = dir.into_path();
-        let repo = Repository::new(dir.clone());
-        repo.init()?;
-        Ok(repo)
+    // Read the content of the environment, the encrypted environment is decrypted in memory.
+    pub fn read_environment(&self, env: &str) -> Result<String, EnvmError> {
+        let content = self.read_raw_environment(env)?;
+        match values::wrapped_data_key(&content) {
+            Some(wrapped) => {
+                let data_key = self.decrypt_content(env, &wrapped)?;
+                values::decrypt_values(&content, &data_key)
+                    .map_err(|err| EnvmError::FailedToDecryptEnvironment(String::from(env), err))
+            }
+            None => Ok(content),
+        }
     }
 
-    fn make_local_env_file(repo: &Repository) -> Result<PathBuf, Box<dyn Error>> {
-        let path = path::get_local_env_path(repo);
-        fs::write(&path, "ENV=local")?;
-        Ok(path)
+    // Read the content of the environment without decrypting the values, which is enough when
+    // only the keys are needed.
+    fn read_raw_environment(&self, env: &str) -> Result<String, EnvmError> {
+        if !self.environment_exists(env) {
+            return Err(EnvmError::MissingTargetEnvironment(String::from(env)));
+        }
+        let content = fs::read_to_string(self.environment_path(env)).unwrap();
+        if !self.is_encrypted(env) {
+            return Ok(content);
+        }
+        self.decrypt_content(env, &content)
     }
 
-    fn make_env_file(repo: &Repository, env: &str) -> Result<PathBuf, Box<dyn Error>> {
-        let path = path::get_env_path(&repo, env);
-        let data = format!("ENV={}", env);
-        fs::write(&path, data)?;
-        Ok(path)
+    // The environment merged with its parents and the `#@include` files expanded, which is what
+    // gets used.
+    pub fn compose_environment(&self, env: &str) -> Result<Composition, EnvmError> {
+        self.inherit(env, false)
     }
 
-    #[test]
-    fn should_use_target_env() -> Result<(), Box<dyn Error>> {
-        let repo = create_envm_repo_use_local_env()?;
-        let local_path = make_local_env_file(&repo)?;
-        make_env_file(&repo, "dev")?;
-
-        repo.use_environment("dev")?;
-
-        let head_path = path::get_current_path(&repo.path);
-        let head = fs::read_to_string(head_path)?;
-        assert_eq!(head, EnvType::Other(String::from("dev")).to_string());
-
-        let contents = fs::read_to_string(local_path)?;
-        assert_eq!(contents, "ENV=dev");
-        fs::remove_dir_all(repo.path)?;
-        Ok(())
+    // The environment and its parents, from the root to the environment itself.
+    pub fn ancestry(&self, env: &str) -> Result<Vec<String>, EnvmError> {
+        let mut ancestry = vec![String::from(env)];
+        while let Some(parent) = self.config.parent(ancestry.last().unwrap()) {
+            if ancestry.contains(parent) {
+                ancestry.push(parent.clone());
+                ancestry.reverse();
+                return Err(EnvmError::CyclicParentEnvironments(ancestry));
+            }
+            if !self.environment_exists(parent) {
+                return Err(EnvmError::MissingParentEnvironment(
+                    ancestry.pop().unwrap(),
+                    parent.clone(),
+                ));
+            }
+            ancestry.push(parent.clone());
+        }
+        ancestry.reverse();
+        Ok(ancestry)
     }
 
-    #[test]
-    fn should_backup_local_env() -> Result<(), Box<dyn Error>> {
-        let repo = create_envm_repo_use_local_env()?;
-        make_local_env_file(&repo)?;
-        make_env_file(&repo, "dev")?;
+    // Every definition of the variable in the parents and the included files, the last one is
+    // the final value.
+    pub fn explain(&self, env: &str, key: &str) -> Result<Vec<Definition>, EnvmError> {
+        let definitions = self.compose_environment(env)?.definitions(key);
+        if definitions.is_empty() {
+            return Err(EnvmError::MissingVariable(
+                String::from(key),
+                String::from(env),
+            ));
+        }
+        Ok(definitions)
+    }
 
-        repo.use_environment("dev")?;
-        let backup_path = path::get_local_backup_path(&repo);
-        assert!(backup_path.exists());
-        let contents = fs::read_to_string(backup_path)?;
-        assert_eq!(contents, "ENV=local");
-        fs::remove_dir_all(repo.path)?;
-        Ok(())
+    // Merge the environment with its parents, the values are not decrypted when `raw` is set. The
+    // stacked environments are merged from left to right, each with its own parents.
+    fn inherit(&self, env: &str, raw: bool) -> Result<Composition, EnvmError> {
+        let env_type = EnvType::from(env);
+        let layers = env_type.layers();
+        if matches!(env_type, EnvType::Stack(_)) && layers.contains(&"local") {
+            return Err(EnvmError::InvalidEnvironmentStack(
+                String::from(env),
+                String::from("the local environment cannot be stacked"),
+            ));
+        }
+        let mut ancestry = vec![];
+        for layer in layers {
+            ancestry.extend(self.ancestry(layer)?);
+        }
+        let mut compositions = vec![];
+        for it in ancestry {
+            let content = if raw {
+                self.read_raw_environment(&it)?
+            } else {
+                self.read_environment(&it)?
+            };
+            compositions.push(self.compose(&it, &content)?);
+        }
+        Ok(Composition::merge(compositions))
     }
 
-    #[test]
-    fn should_use_backup_local_env_use_local_env() -> Result<(), Box<dyn Error>> {
-        let repo = create_envm_repo_use_local_env()?;
-        let local_path = make_local_env_file(&repo)?;
-        make_env_file(&repo, "dev")?;
-        repo.use_environment("dev")?;
-        let backup_path = path::get_local_backup_path(&repo);
-        fs::write(&backup_path, "ENV=backup")?;
+    // The included files are relative to the repository, and read as they are so they cannot be
+    // encrypted.
+    fn compose(&self, env: &str, content: &str) -> Result<Composition, EnvmError> {
+        let file_path = path::get_env_or_local_path(self, env);
+        let file = file_path.file_name().unwrap().to_str().unwrap();
+        let read = |included: &str| {
+            let content = fs::read_to_string(self.path.join(included))
+                .map_err(|err| format!("cannot read {}: {}", included, err))?;
+            if crypto::is_encrypted(&content) || values::has_encrypted_values(&content) {
+                return Err(format!("cannot include the encrypted file {}", included));
+            }
+            Ok(content)
+        };
+        includes::compose(file, content, &read)
+            .map_err(|err| EnvmError::FailedToInclude(String::from(env), err))
+    }
 
-        // Sync repository state with file system
-        let repo = Repository::load(repo.path)?;
-        repo.use_environment("local")?;
-        let contents = fs::read_to_string(local_path)?;
-        assert_eq!(contents, "ENV=backup");
-        fs::remove_dir_all(repo.path)?;
-        Ok(())
+    // Write the content of the environment, it's encrypted again if the environment is encrypted.
+    fn write_environment(&self, env: &str, content: &str) -> Result<(), EnvmError> {
+        self.check_unmodified(env)?;
+        if self.recipients().is_empty() && self.is_encrypted_to_recipients(env) {
+            return Err(EnvmError::MissingRecipients(String::from(env)));
+        }
+        let env_path = self.environment_path(env);
+        let content = if self.is_encrypted(env) {
+            self.encrypt_content(env, content, false, true)?
+        } else if self.has_encrypted_values(env) {
+            let previous = fs::read_to_string(&env_path).unwrap();
+            self.encrypt_values(env, content, Some(&previous), false)?
+        } else {
+            content.to_string()
+        };
+        fs::write(env_path, content).unwrap();
+        self.reseal(env)
     }
 
-    fn make_template_env_file(repo: &Repository) -> Result<PathBuf, Box<dyn Error>> {
-        let template_path = path::get_template_env_path(repo);
-        fs::write(&template_path, "ENV=")?;
-        Ok(template_path)
+    // Encrypt each value with the data key of the previous content, or a new data key if there
+    // is no previous content, the data key is encrypted like the whole file.
+    fn encrypt_values(
+        &self,
+        env: &str,
+        content: &str,
+        previous: Option<&str>,
+        confirm: bool,
+    ) -> Result<String, EnvmError> {
+        let (data_key, wrapped) = match previous.and_then(values::wrapped_data_key) {
+            Some(wrapped) => (self.decrypt_content(env, &wrapped)?, wrapped),
+            None => {
+                let data_key = values::generate_data_key();
+                let wrapped = self.encrypt_content(env, &data_key, confirm, false)?;
+                (data_key, wrapped)
+            }
+        };
+        values::encrypt_values(content, &data_key, &wrapped, previous)
+            .map_err(|err| EnvmError::FailedToEncryptEnvironment(String::from(env), err))
     }
 
-    #[test]
-    fn should_new_target_env_file() -> Result<(), Box<dyn Error>> {
-        let repo = create_envm_repo_use_local_env()?;
-        make_template_env_file(&repo)?;
+    // Encrypt to the recipients if there are any, otherwise with the passphrase.
+    fn encrypt_content(
+        &self,
+        env: &str,
+        content: &str,
+        confirm: bool,
+        armor: bool,
+    ) -> Result<String, EnvmError> {
+        let recipients = self.recipients();
+        let encrypted = if recipients.is_empty() {
+            crypto::encrypt(content, &self.passphrase(confirm)?, armor)
+        } else {
+            crypto::encrypt_to_recipients(content, &recipients, armor)
+        };
+        encrypted.map_err(|err| EnvmError::FailedToEncryptEnvironment(String::from(env), err))
+    }
 
-        repo.new_environment("dev")?;
+    // The key is only given to the agent once it decrypts the content, and a wrong one is
+    // forgotten so it's asked again next time.
+    fn decrypt_content(&self, env: &str, content: &str) -> Result<String, EnvmError> {
+        let (decrypted, name, cache) = if crypto::is_passphrase_encrypted(content) {
+            let passphrase = self.passphrase(false)?;
+            (
+                crypto::decrypt(content, &passphrase).map(|it| (it, passphrase)),
+                agent::passphrase_name(&self.path),
+                &self.passphrase,
+            )
+        } else {
+            let identity = self.identity()?;
+            (
+                crypto::decrypt_with_identity(content, &identity).map(|it| (it, identity)),
+                String::from(agent::IDENTITY),
+                &self.identity,
+            )
+        };
+        match decrypted {
+            Ok((decrypted, key)) => {
+                add_agent_key(&name, &key);
+                Ok(decrypted)
+            }
+            Err(err) => {
+                *cache.borrow_mut() = None;
+                forget_agent_key(&name);
+                Err(EnvmError::FailedToDecryptEnvironment(
+                    String::from(env),
+                    err,
+                ))
+            }
+        }
+    }
 
-        let dev_path = path::get_env_path(&repo, "dev");
-        assert!(dev_path.exists());
-        fs::remove_dir_all(repo.path)?;
-        Ok(())
+    // The identity file in `ENVM_IDENTITY_FILE` is preferred over the agent, like the passphrase.
+    fn identity(&self) -> Result<String, EnvmError> {
+        if let Some(identity) = self.identity.borrow().as_ref() {
+            return Ok(identity.clone());
+        }
+        let cached = match env::var_os(crypto::IDENTITY_FILE_VARIABLE) {
+            Some(_) => None,
+            None => agent_key(agent::IDENTITY),
+        };
+        let identity = match cached {
+            Some(it) => it,
+            None => crypto::read_identity().map_err(EnvmError::FailedToReadIdentity)?,
+        };
+        *self.identity.borrow_mut() = Some(identity.clone());
+        Ok(identity)
     }
 
-    #[test]
-    fn should_new_local_env_file_by_config() -> Result<(), Box<dyn Error>> {
-        let repo = create_envm_repo_use_local_env()?;
-        make_template_env_file(&repo)?;
+    // Whether the environment, or the data key of its values, is encrypted to the recipients
+    // instead of the passphrase, which cannot fall back to the passphrase silently.
+    fn is_encrypted_to_recipients(&self, env: &str) -> bool {
+        let content = fs::read_to_string(self.environment_path(env)).unwrap_or_default();
+        let encrypted = if self.is_encrypted(env) {
+            Some(content)
+        } else {
+            values::wrapped_data_key(&content)
+        };
+        encrypted.is_some_and(|it| !crypto::is_passphrase_encrypted(&it))
+    }
 
-        repo.new_environment("local")?;
+    // The public keys in `.envm/recipients`, one per line.
+    pub fn recipients(&self) -> Vec<String> {
+        fs::read_to_string(path::get_recipients_path(self))
+            .unwrap_or_default()
+            .lines()
+            .map(str::trim)
+            .filter(|it| !it.is_empty() && !it.starts_with('#'))
+            .map(String::from)
+            .collect()
+    }
 
-        let local_path = path::get_local_env_path(&repo);
-        assert!(local_path.exists());
-        fs::remove_dir_all(repo.path)?;
-        Ok(())
+    pub fn add_recipient(&self, recipient: &str) -> Result<Vec<String>, EnvmError> {
+        crypto::parse_recipient(recipient).map_err(EnvmError::InvalidRecipient)?;
+        let mut recipients = self.recipients();
+        if recipients.iter().any(|it| it == recipient) {
+            return Err(EnvmError::RecipientAlreadyExists(String::from(recipient)));
+        }
+        recipients.push(String::from(recipient));
+        self.update_recipients(&recipients)
     }
 
-    #[test]
-    fn should_list_env() -> Result<(), Box<dyn Error>> {
-        let repo = create_envm_repo_use_local_env()?;
-        make_template_env_file(&repo)?;
-        make_env_file(&repo, "dev")?;
-        make_env_file(&repo, "production")?;
+    pub fn remove_recipient(&self, recipient: &str) -> Result<Vec<String>, EnvmError> {
+        let mut recipients = self.recipients();
+        if !recipients.iter().any(|it| it == recipient) {
+            return Err(EnvmError::MissingRecipient(String::from(recipient)));
+        }
+        recipients.retain(|it| it != recipient);
+        self.update_recipients(&recipients)
+    }
 
-        assert_eq!(
-            repo.list_environments().sort(),
-            vec!["dev", "production"].sort()
-        );
-        fs::remove_dir_all(repo.path)?;
-        Ok(())
+    // Save the recipients and encrypt the encrypted environments again, all of them are decrypted
+    // before saving so nothing is changed if any of them cannot be decrypted. The environments
+    // with encrypted values get a new data key, since the removed recipient may know the old one.
+    // Return the environments encrypted again.
+    fn update_recipients(&self, recipients: &[String]) -> Result<Vec<String>, EnvmError> {
+        let mut decrypted = vec![];
+        for env in self.list_environments() {
+            if self.is_encrypted(&env) || self.has_encrypted_values(&env) {
+                self.check_unmodified(&env)?;
+                if recipients.is_empty() && self.is_encrypted_to_recipients(&env) {
+                    return Err(EnvmError::MissingRecipients(env));
+                }
+                let content = self.read_environment(&env)?;
+                decrypted.push((env, content));
+            }
+        }
+        let mut content = recipients.join("\n");
+        if !content.is_empty() {
+            content.push('\n');
+        }
+        fs::write(path::get_recipients_path(self), content).unwrap();
+        for (env, content) in &decrypted {
+            if self.has_encrypted_values(env) {
+                let encrypted = self.encrypt_values(env, content, None, false)?;
+                fs::write(path::get_env_path(self, env), encrypted).unwrap();
+                self.reseal(env)?;
+            } else {
+                self.write_environment(env, content)?;
+            }
+        }
+        Ok(decrypted.into_iter().map(|(env, _)| env).collect())
     }
 
-    #[test]
-    fn should_remove_env() -> Result<(), Box<dyn Error>> {
-        let repo = create_envm_repo_use_local_env()?;
-        make_template_env_file(&repo)?;
-        make_env_file(&repo, "dev")?;
+    fn passphrase(&self, confirm: bool) -> Result<String, EnvmError> {
+        if let Some(passphrase) = self.passphrase.borrow().as_ref() {
+            return Ok(passphrase.clone());
+        }
+        // `ENVM_PASSPHRASE` is set on purpose, so it's preferred over the agent.
+        let cached = match env::var_os(crypto::PASSPHRASE_VARIABLE) {
+            Some(_) => None,
+            None => agent_key(&agent::passphrase_name(&self.path)),
+        };
+        let passphrase = match cached {
+            Some(it) => it,
+            None => crypto::read_passphrase(confirm).map_err(EnvmError::FailedToReadPassphrase)?,
+        };
+        *self.passphrase.borrow_mut() = Some(passphrase.clone());
+        Ok(passphrase)
+    }
 
-        repo.remove_environment("dev")?;
-        let dev_path = path::get_env_path(&repo, "dev");
-        assert!(!dev_path.exists());
+    // Replace the plaintext environment file with the encrypted one, or encrypt the values in
+    // place when `values` is set so the keys can still be reviewed in diffs.
+    pub fn encrypt_environment(&self, env: &str, values: bool) -> Result<(), EnvmError> {
+        if matches!(EnvType::from(env), EnvType::Local) {
+            return Err(EnvmError::CannotEncryptLocalEnvironment);
+        }
+        if self.is_encrypted(env) || self.has_encrypted_values(env) {
+            return Err(EnvmError::EnvironmentAlreadyEncrypted(String::from(env)));
+        }
+        let env_path = path::get_env_path(self, env);
+        if !env_path.exists() {
+            return Err(EnvmError::MissingTargetEnvironment(String::from(env)));
+        }
+        self.check_unmodified(env)?;
+        let content = fs::read_to_string(&env_path).unwrap();
+        if crypto::is_encrypted(&content) {
+            return Err(EnvmError::EnvironmentAlreadyEncrypted(String::from(env)));
+        }
+        if values {
+            let encrypted = self.encrypt_values(env, &content, None, true)?;
+            fs::write(env_path, encrypted).unwrap();
+            return self.reseal(env);
+        }
+        let encrypted = self.encrypt_content(env, &content, true, true)?;
+        fs::write(path::get_encrypted_env_path(self, env), encrypted).unwrap();
+        fs::remove_file(env_path).unwrap();
+        self.reseal(env)
+    }
 
-        fs::remove_dir_all(repo.path)?;
-        Ok(())
+    // Replace the encrypted environment file with the plaintext one.
+    pub fn decrypt_environment(&self, env: &str) -> Result<(), EnvmError> {
+        if !self.is_encrypted(env) && !self.has_encrypted_values(env) {
+            return Err(EnvmError::EnvironmentNotEncrypted(String::from(env)));
+        }
+        self.check_unmodified(env)?;
+        let env_path = path::get_env_path(self, env);
+        // The plaintext file next to the encrypted one is not written by envm, so it's kept.
+        if self.is_encrypted(env) && env_path.exists() {
+            return Err(EnvmError::PlaintextEnvironmentExists(String::from(env)));
+        }
+        let content = self.read_environment(env)?;
+        fs::write(env_path, content).unwrap();
+        let encrypted_path = path::get_encrypted_env_path(self, env);
+        if encrypted_path.exists() {
+            fs::remove_file(encrypted_path).unwrap();
+        }
+        self.reseal(env)
     }
 
-    #[test]
-    fn should_not_remove_current_env() -> Result<(), Box<dyn Error>> {
+    pub fn compare_to_template(
+        &self,
+        target_configuration: &Configuration,
+    ) -> (Option<Vec<String>>, Option<Vec<String>>) {
+        // detect configuration format
+        //   support format
+        //     - json
+        //     - toml
+        //     - yaml
+        //     - dotenv
+        // read template as hash map
+        // read target configuration as hash map
+        // compare two hash map
+        let template_configuration = configuration::parse(&path::get_template_env_path(self));
+        configuration::compare(&template_configuration, target_configuration)
+    }
+
+    // Add the variables missing from the environment base on template, return the added lines.
+    pub fn sync_environment(&self, env: &str, dry_run: bool) -> Result<Vec<String>, EnvmError> {
+        let template_path = path::get_template_env_path(self);
+        if !template_path.exists() {
+            return Err(EnvmError::MissingTemplateEnvironment(
+                self.config.template().clone(),
+            ));
+        }
+        let mut template = Document::parse(&fs::read_to_string(template_path).unwrap());
+        let content = self.read_environment(env)?;
+        // The variables from the parents and the included files are not added, or they would
+        // override them.
+        for key in self.inherit(env, true)?.sources().keys() {
+            template.remove(key);
+        }
+        let mut target = Document::parse(&content);
+        let inserted = configuration::sync(&template, &mut target);
+        if !dry_run && !inserted.is_empty() {
+            self.write_environment(env, &target.to_string())?;
+        }
+        Ok(inserted)
+    }
+
+    // Remove the variables which no longer appear in template, except the local only variables,
+    // the environment file will be backup before writing, return the removed variables.
+    pub fn prune_environment(&self, env: &str, dry_run: bool) -> Result<Vec<String>, EnvmError> {
+        let template_path = path::get_template_env_path(self);
+        if !template_path.exists() {
+            return Err(EnvmError::MissingTemplateEnvironment(
+                self.config.template().clone(),
+            ));
+        }
+        let template = Document::parse(&fs::read_to_string(template_path).unwrap());
+        let mut target = Document::parse(&self.read_environment(env)?);
+        // The key defined more than once is removed once.
+        let mut seen = BTreeSet::new();
+        let extra: Vec<String> = target
+            .keys()
+            .into_iter()
+            .filter(|it| !template.contains_key(it) && !self.config.local_only().contains(it))
+            .filter(|it| seen.insert(it.clone()))
+            .collect();
+        if dry_run || extra.is_empty() {
+            return Ok(extra);
+        }
+        // The encrypted environment is backup as it is, so the backup is encrypted as well.
+        self.backup_environment(&self.environment_path(env))?;
+        for key in &extra {
+            target.remove(key);
+        }
+        self.write_environment(env, &target.to_string())?;
+        Ok(extra)
+    }
+
+    // Generate the template from all environments, or add the variables missing from the template
+    // when updating, return the variables in the template that are written.
+    pub fn generate_template(
+        &self,
+        update: bool,
+        placeholder: &str,
+    ) -> Result<Vec<String>, EnvmError> {
+        let template_path = path::get_template_env_path(self);
+        if !update && template_path.exists() {
+            return Err(EnvmError::TemplateEnvironmentAlreadyExists(
+                self.config.template().clone(),
+            ));
+        }
+        if update && !template_path.exists() {
+            return Err(EnvmError::MissingTemplateEnvironment(
+                self.config.template().clone(),
+            ));
+        }
+        let mut envs = self.list_environments();
+        envs.sort();
+        if envs.is_empty() {
+            return Err(EnvmError::MissingEnvironments);
+        }
+        let mut sources: Vec<Document> = vec![];
+        for env in &envs {
+            sources.push(Document::parse(&self.compose_environment(env)?.content()));
+        }
+        let generated = configuration::generate_template(&sources, placeholder);
+        let (template, written) = if update {
+            let mut template = Document::parse(&fs::read_to_string(&template_path).unwrap());
+            let inserted = configuration::sync(&generated, &mut template);
+            (template, inserted)
+        } else {
+            let lines = generated
+                .keys()
+                .iter()
+                .map(|it| format!("{}={}", it, placeholder))
+                .collect();
+            (generated, lines)
+        };
+        if !written.is_empty() {
+            fs::write(template_path, template.to_string()).unwrap();
+        }
+        Ok(written)
+    }
+
+    fn backup_environment(&self, env_path: &Path) -> Result<PathBuf, EnvmError> {
+        let backups_path = path::get_backups_path(self);
+        fs::create_dir_all(&backups_path).map_err(|_| EnvmError::FailedToBackupEnvironment)?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let filename = env_path.file_name().unwrap().to_str().unwrap();
+        let backup_path = backups_path.join(format!("{}.{}", filename, timestamp));
+        fs::copy(env_path, &backup_path).map_err(|_| EnvmError::FailedToBackupEnvironment)?;
+        Ok(backup_path)
+    }
+
+    // The schema described by the annotations in template, and the schema file which takes
+    // precedence over the annotations.
+    pub fn schema(&self) -> Result<Schema, EnvmError> {
+        let template_path = path::get_template_env_path(self);
+        let mut schema = match fs::read_to_string(template_path) {
+            Ok(content) => Schema::from_template(&Document::parse(&content)),
+            Err(_) => Schema::default(),
+        };
+        // The default schema is optional, but the one configured must exist.
+        let schema_path = path::get_schema_path(self);
+        let content = match fs::read_to_string(&schema_path) {
+            Ok(it) => it,
+            Err(_) if self.config.schema().is_none() => return Ok(schema),
+            Err(err) => {
+                return Err(EnvmError::FailedToParseSchema(format!(
+                    "cannot read {}: {}",
+                    schema_path.display(),
+                    err
+                )))
+            }
+        };
+        schema.merge(json_schema::parse(&content).map_err(EnvmError::FailedToParseSchema)?);
+        Ok(schema)
+    }
+
+    // The fingerprint of the environment, with a random salt unless the salt is given to compare
+    // with another fingerprint.
+    pub fn fingerprint(&self, env: &str, salt: Option<&str>) -> Result<Fingerprint, EnvmError> {
+        let variables = self.compose_environment(env)?.variables();
+        Ok(match salt {
+            Some(salt) => Fingerprint::with_salt(&variables, salt),
+            None => Fingerprint::new(&variables),
+        })
+    }
+
+    pub fn redactor(&self, reveal: bool) -> Result<Redactor, EnvmError> {
+        Ok(Redactor::new(self.config.secrets(), self.schema()?, reveal))
+    }
+
+    // Append a line to the audit log in `.envm`, with the time and the user.
+    pub fn audit(&self, action: &str) -> Result<(), EnvmError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let user = env::var("USER").unwrap_or_else(|_| String::from("unknown"));
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path::get_audit_log_path(self))
+            .map_err(EnvmError::FailedToWriteAuditLog)?;
+        writeln!(file, "{} {} {}", timestamp, user, action)
+            .map_err(EnvmError::FailedToWriteAuditLog)
+    }
+
+    pub fn export_schema(&self) -> Result<String, EnvmError> {
+        let template_path = path::get_template_env_path(self);
+        if !template_path.exists() {
+            return Err(EnvmError::MissingTemplateEnvironment(
+                self.config.template().clone(),
+            ));
+        }
+        let template = Document::parse(&fs::read_to_string(template_path).unwrap());
+        let exported = json_schema::export(&self.schema()?, &template.keys());
+        Ok(serde_json::to_string_pretty(&exported).unwrap())
+    }
+
+    pub fn verify_environment(&self, env: &str) -> Result<Verification, EnvmError> {
+        let template_path = path::get_template_env_path(self);
+        if !template_path.exists() {
+            return Err(EnvmError::MissingTemplateEnvironment(
+                self.config.template().clone(),
+            ));
+        }
+        // The encrypted values are not decrypted and the references are not resolved, only the
+        // keys and the plaintext values are checked.
+        let composition = self.inherit(env, true)?;
+        let target = composition.variables();
+        let schema = self.schema()?;
+        let (missing, extra) = self.compare_to_template(&target);
+        let mut empty_required: Vec<String> = target
+            .iter()
+            .filter(|(key, value)| {
+                schema.is_required_in(key, env) && dotenv::unquote(value).is_empty()
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        // The values are checked after the `${VAR}` references are resolved, except the ones
+        // referring to the values which cannot be checked.
+        let interpolation = interpolation::interpolate(&target, self.config.interpolate_from_env());
+        // The lines are in the files where the variables come from.
+        let all_entries = composition.entries();
+        let entries: Vec<Entry> = all_entries
+            .iter()
+            .filter(|it| {
+                !is_opaque(&it.value)
+                    && interpolation::references(&it.value)
+                        .iter()
+                        .all(|name| !target.get(name).is_some_and(|it| is_opaque(it)))
+            })
+            .map(|it| match interpolation.values.get(&it.key) {
+                Some(value) if !interpolation::references(&it.value).is_empty() => Entry {
+                    value: lint::quote(value),
+                    ..it.clone()
+                },
+                _ => it.clone(),
+            })
+            .collect();
+        let mut invalid = validation::validate(&schema, &entries);
+        for (key, message) in interpolation.problems {
+            if let Some(entry) = all_entries.iter().rev().find(|it| it.key == key) {
+                invalid.push(Issue {
+                    line: entry.line,
+                    key,
+                    value: dotenv::unquote(&entry.value),
+                    message,
+                });
+            }
+        }
+        let references = invalid
+            .iter()
+            .map(|it| {
+                let references = references(&target, &interpolation.values, &it.key);
+                (it.key.clone(), references)
+            })
+            .collect();
+        // The invalid values of the environment file come first, then the included files.
+        let sources = composition.sources();
+        invalid.sort_by_key(|it| (sources.get(&it.key).cloned(), it.line));
+        let patterns = self.placeholder_patterns()?;
+        let plaintext: Configuration = target
+            .iter()
+            .filter(|(_, value)| !is_opaque(value))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let placeholders = configuration::find_placeholders(
+            &configuration::parse(&template_path),
+            &plaintext,
+            &patterns,
+        );
+        let mut missing = missing.unwrap_or_default();
+        for key in schema.required_in(env) {
+            if !target.contains_key(&key) && !missing.contains(&key) {
+                missing.push(key);
+            }
+        }
+        let mut extra = extra.unwrap_or_default();
+        let signature = self.check_signatures(env)?;
+        missing.sort();
+        extra.sort();
+        empty_required.sort();
+        Ok(Verification {
+            file: self
+                .environment_path(EnvType::from(env).layers().last().unwrap())
+                .file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string(),
+            missing,
+            extra,
+            empty_required,
+            invalid,
+            placeholders,
+            tampered: signature == Some(Status::Modified),
+            unsigned: signature == Some(Status::Unsigned),
+            sources,
+            references,
+        })
+    }
+
+    // The variables of the environment in the file order, with the `${VAR}` references resolved
+    // unless `raw` is set. Return the problems of interpolation as the error.
+    pub fn show_environment(
+        &self,
+        env: &str,
+        raw: bool,
+        process_env: bool,
+    ) -> Result<Vec<(String, String, References)>, EnvmError> {
+        let content = self.compose_environment(env)?.content();
+        let entries = dotenv::entries(&content);
+        if raw {
+            return Ok(entries
+                .into_iter()
+                .map(|it| (it.key, dotenv::unquote(&it.value), vec![]))
+                .collect());
+        }
+        let variables = dotenv::parse(&content);
+        let interpolation = interpolation::interpolate(
+            &variables,
+            process_env || self.config.interpolate_from_env(),
+        );
+        if !interpolation.problems.is_empty() {
+            return Err(EnvmError::FailedToInterpolate(interpolation.problems));
+        }
+        // The `@cmd:` and `@file:` references are resolved like on use, so show and exec see the
+        // same values as the materialized file.
+        let mut resolver = Resolver::new(self.config.references(), self.permissions);
+        let mut errors = vec![];
+        let mut shown = vec![];
+        for entry in entries {
+            let mut value = interpolation.values[&entry.key].clone();
+            match resolver.resolve(&value) {
+                Some(Ok(resolved)) => value = resolved,
+                Some(Err(err)) => errors.push((entry.key.clone(), err)),
+                None => {}
+            }
+            let references = references(&variables, &interpolation.values, &entry.key);
+            shown.push((entry.key, value, references));
+        }
+        if !errors.is_empty() {
+            return Err(EnvmError::FailedToResolveReferences(errors));
+        }
+        Ok(shown)
+    }
+
+    // Check the environment before using it, return the verification if it has any problem, even
+    // the ones allowed under its strictness so they are still reported. The local environment is
+    // not checked, and only the signature is checked in the repository without template.
+    pub fn check_before_use(&self, env: &str) -> Result<Option<Verification>, EnvmError> {
+        let env_type = EnvType::from(env);
+        let exists = env_type
+            .layers()
+            .iter()
+            .all(|it| self.environment_exists(it));
+        if matches!(env_type, EnvType::Local) || !exists {
+            return Ok(None);
+        }
+        let verification = if path::get_template_env_path(self).exists() {
+            self.verify_environment(env)?
+        } else {
+            let signature = self.check_signatures(env)?;
+            Verification {
+                tampered: signature == Some(Status::Modified),
+                unsigned: signature == Some(Status::Unsigned),
+                ..Verification::default()
+            }
+        };
+        if verification.is_clean() {
+            Ok(None)
+        } else {
+            Ok(Some(verification))
+        }
+    }
+
+    // Lint the managed files, the fixable problems are fixed before linting when `fix` is set,
+    // return the remaining problems of each file.
+    pub fn lint_files(&self, fix: bool) -> Result<Vec<(String, Vec<Problem>)>, EnvmError> {
+        let mut results = vec![];
+        for (label, file_path) in self.managed_files() {
+            let mut content = fs::read_to_string(&file_path).unwrap();
+            if fix {
+                let fixed = lint::fix(&content, self.config.lint());
+                if fixed != content {
+                    self.check_unmodified_file(&label, &file_path)?;
+                    fs::write(&file_path, &fixed).unwrap();
+                    self.reseal_file(&label, &file_path)?;
+                    content = fixed;
+                }
+            }
+            let filename = file_path.file_name().unwrap().to_str().unwrap();
+            results.push((
+                filename.to_string(),
+                lint::lint(&content, self.config.lint()),
+            ));
+        }
+        Ok(results)
+    }
+
+    // Format the files in the canonical style, default to all managed files, the environments can
+    // also be reordered to follow the template. Return the files which are changed, or would be
+    // changed in check mode.
+    pub fn format_files(
+        &self,
+        envs: &[String],
+        reorder: bool,
+        check: bool,
+    ) -> Result<Vec<String>, EnvmError> {
+        let template_path = path::get_template_env_path(self);
+        let files = if envs.is_empty() {
+            self.managed_files()
+        } else {
+            let mut files = vec![];
+            for env in envs {
+                let env_path = path::get_env_or_local_path(self, env);
+                if !env_path.exists() {
+                    return Err(EnvmError::MissingTargetEnvironment(env.clone()));
+                }
+                files.push((env.clone(), env_path));
+            }
+            files
+        };
+        let template = if reorder {
+            if !template_path.exists() {
+                return Err(EnvmError::MissingTemplateEnvironment(
+                    self.config.template().clone(),
+                ));
+            }
+            Some(Document::parse(
+                &fs::read_to_string(&template_path).unwrap(),
+            ))
+        } else {
+            None
+        };
+
+        let mut changed = vec![];
+        for (label, file_path) in files {
+            let content = fs::read_to_string(&file_path).unwrap();
+            let formatted = match &template {
+                Some(template) if file_path != template_path => {
+                    format::format(&format::reorder(&content, template))
+                }
+                _ => format::format(&content),
+            };
+            if formatted == content {
+                continue;
+            }
+            if !check {
+                self.check_unmodified_file(&label, &file_path)?;
+                fs::write(&file_path, &formatted).unwrap();
+                self.reseal_file(&label, &file_path)?;
+            }
+            let filename = file_path.file_name().unwrap().to_str().unwrap();
+            changed.push(filename.to_string());
+        }
+        Ok(changed)
+    }
+
+    // Sign the environment files, default to all environments, return the signed environments.
+    // Signing all environments starts a new manifest, so the removed files are dropped.
+    pub fn sign_environments(&self, envs: &[String]) -> Result<Vec<String>, EnvmError> {
+        let key = self.signing_key(true)?;
+        let (envs, mut manifest) = if envs.is_empty() {
+            (self.list_environments(), Manifest::default())
+        } else {
+            for env in envs {
+                if !self.environment_exists(env) {
+                    return Err(EnvmError::MissingTargetEnvironment(env.clone()));
+                }
+            }
+            (envs.to_vec(), self.manifest(&key)?)
+        };
+        for env in &envs {
+            // The parents and the included files are signed as well, since they are part of the
+            // environment being used.
+            for it in self.ancestry(env)? {
+                self.sign_environment(&mut manifest, &key, &it);
+            }
+            for file in self.inherit(env, true)?.includes() {
+                self.sign_file(&mut manifest, &key, file);
+            }
+        }
+        fs::write(path::get_signatures_path(self), manifest.seal(&key)).unwrap();
+        Ok(envs)
+    }
+
+    // Whether the environment file is the one signed, nothing is checked until the environments
+    // are signed. The local environment is not signed since it's written by `use`.
+    pub fn check_signature(&self, env: &str) -> Result<Option<Status>, EnvmError> {
+        if matches!(EnvType::from(env), EnvType::Local) {
+            return Ok(None);
+        }
+        let env_path = self.environment_path(env);
+        self.check_file_signature(env_path.file_name().unwrap().to_str().unwrap())
+    }
+
+    // The file is relative to the repository.
+    fn check_file_signature(&self, file: &str) -> Result<Option<Status>, EnvmError> {
+        if !path::get_signatures_path(self).exists() {
+            return Ok(None);
+        }
+        let key = self.signing_key(false)?;
+        let manifest = self.manifest(&key)?;
+        Ok(Some(manifest.check(
+            &key,
+            file,
+            &fs::read(self.path.join(file)).unwrap_or_default(),
+        )))
+    }
+
+    // The signature of the environment with its parents and the included files, and of every
+    // layer of the stacked environments, is the worst one of them.
+    fn check_signatures(&self, env: &str) -> Result<Option<Status>, EnvmError> {
+        let mut statuses = vec![];
+        for layer in EnvType::from(env).layers() {
+            for it in self.ancestry(layer)? {
+                statuses.push(self.check_signature(&it)?);
+            }
+        }
+        for file in self.inherit(env, true)?.includes() {
+            statuses.push(self.check_file_signature(file)?);
+        }
+        let mut status = None;
+        for it in statuses {
+            status = match (status, it) {
+                (Some(Status::Modified), _) | (_, Some(Status::Modified)) => Some(Status::Modified),
+                (Some(Status::Unsigned), _) | (_, Some(Status::Unsigned)) => Some(Status::Unsigned),
+                (status, it) => it.or(status),
+            };
+        }
+        Ok(status)
+    }
+
+    // The environment modified outside envm cannot be written, since it's signed again after
+    // writing and the changes would be trusted without review.
+    fn check_unmodified(&self, env: &str) -> Result<(), EnvmError> {
+        match self.check_signature(env)? {
+            Some(Status::Modified) => Err(EnvmError::ModifiedEnvironment(String::from(env))),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_unmodified_file(&self, label: &str, file_path: &Path) -> Result<(), EnvmError> {
+        if *file_path == path::get_template_env_path(self) {
+            return Ok(());
+        }
+        self.check_unmodified(label)
+    }
+
+    // Sign the environment again after envm writes it, so only the changes made outside envm are
+    // reported.
+    fn reseal(&self, env: &str) -> Result<(), EnvmError> {
+        let signatures_path = path::get_signatures_path(self);
+        if matches!(EnvType::from(env), EnvType::Local) || !signatures_path.exists() {
+            return Ok(());
+        }
+        let key = self.signing_key(false)?;
+        let mut manifest = self.manifest(&key)?;
+        self.sign_environment(&mut manifest, &key, env);
+        fs::write(signatures_path, manifest.seal(&key)).unwrap();
+        Ok(())
+    }
+
+    // The managed files are labeled by the environment name, except the template and local.
+    fn reseal_file(&self, label: &str, file_path: &Path) -> Result<(), EnvmError> {
+        if *file_path == path::get_template_env_path(self) {
+            return Ok(());
+        }
+        self.reseal(label)
+    }
+
+    // Sign both the plaintext and the encrypted file of the environment, the one which doesn't
+    // exist is removed from the manifest.
+    fn sign_environment(&self, manifest: &mut Manifest, key: &[u8], env: &str) {
+        for env_path in [
+            path::get_env_path(self, env),
+            path::get_encrypted_env_path(self, env),
+        ] {
+            self.sign_file(
+                manifest,
+                key,
+                env_path.file_name().unwrap().to_str().unwrap(),
+            );
+        }
+    }
+
+    // The file is relative to the repository.
+    fn sign_file(&self, manifest: &mut Manifest, key: &[u8], file: &str) {
+        match fs::read(self.path.join(file)) {
+            Ok(content) => manifest.sign(key, file, &content),
+            Err(_) => manifest.remove(file),
+        }
+    }
+
+    fn manifest(&self, key: &[u8]) -> Result<Manifest, EnvmError> {
+        match fs::read_to_string(path::get_signatures_path(self)) {
+            Ok(content) => Manifest::parse(&content, key).map_err(EnvmError::InvalidSignatures),
+            Err(_) => Ok(Manifest::default()),
+        }
+    }
+
+    fn signing_key(&self, create: bool) -> Result<Vec<u8>, EnvmError> {
+        if let Some(key) = self.signing_key.borrow().as_ref() {
+            return Ok(key.clone());
+        }
+        let key = signature::read_key(create).map_err(EnvmError::FailedToReadSigningKey)?;
+        *self.signing_key.borrow_mut() = Some(key.clone());
+        Ok(key)
+    }
+
+    fn placeholder_patterns(&self) -> Result<Vec<Regex>, EnvmError> {
+        self.config
+            .placeholders()
+            .iter()
+            .map(|it| Regex::new(it))
+            .collect::<Result<Vec<Regex>, regex::Error>>()
+            .map_err(EnvmError::InvalidPlaceholderPattern)
+    }
+
+    // Scan the environments for the production secrets reused in other environments and the live
+    // credentials in development environments, the local environment is skipped since it's a
+    // copy of the using one.
+    pub fn scan_secrets(&self) -> Result<Vec<Finding>, EnvmError> {
+        let mut names = self.list_environments();
+        names.sort();
+        let mut envs: Vec<secrets::Environment> = vec![];
+        for name in names {
+            envs.push(secrets::Environment {
+                tier: self.config.tier(&name),
+                variables: self.compose_environment(&name)?.variables(),
+                name,
+            });
+        }
+        Ok(secrets::scan(
+            &envs,
+            &self.schema()?,
+            &self.placeholder_patterns()?,
+            &self.salt(),
+        ))
+    }
+
+    // The random salt of the repository to key the fingerprints of the values, it's generated at
+    // the first use and kept under .envm so the fingerprints are the same between the reports.
+    fn salt(&self) -> Vec<u8> {
+        let salt_path = path::get_salt_path(self);
+        if let Ok(content) = fs::read_to_string(&salt_path) {
+            return content.trim().as_bytes().to_vec();
+        }
+        let mut random = [0u8; 32];
+        getrandom::getrandom(&mut random).unwrap();
+        let salt: String = random.iter().map(|it| format!("{:02x}", it)).collect();
+        fs::write(&salt_path, format!("{}\n", salt)).unwrap();
+        salt.into_bytes()
+    }
+
+    pub fn current_env(&self) -> &EnvType {
+        &self.current_env
+    }
+
+    // The template, local and environment files which exist on the disk, paired with a label to
+    // show the user where the variables come from. The encrypted environments are not included.
+    pub fn managed_files(&self) -> Vec<(String, PathBuf)> {
+        let mut files = vec![
+            (String::from("template"), path::get_template_env_path(self)),
+            (String::from("local"), path::get_local_env_path(self)),
+        ];
+        let mut envs = self.list_environments();
+        envs.sort();
+        if !self.config.local_dimensions().is_empty() {
+            let local_paths: BTreeSet<PathBuf> = envs
+                .iter()
+                .map(|it| path::get_local_env_path_of(self, it))
+                .collect();
+            files.extend(
+                local_paths
+                    .into_iter()
+                    .map(|it| (String::from("local"), it)),
+            );
+        }
+        for env in envs {
+            let env_path = path::get_env_path(self, &env);
+            files.push((env, env_path));
+        }
+        files.into_iter().filter(|(_, it)| it.exists()).collect()
+    }
+
+    // Every file managed by envm including the backups.
+    pub fn sources(&self) -> Vec<(String, PathBuf)> {
+        let mut sources = self.managed_files();
+        sources.push((String::from("backup"), path::get_local_backup_path(self)));
+        if let Ok(backups) = fs::read_dir(path::get_backups_path(self)) {
+            let mut backups: Vec<PathBuf> = backups.map(|it| it.unwrap().path()).collect();
+            backups.sort();
+            for backup in backups {
+                let filename = backup.file_name().unwrap().to_str().unwrap();
+                sources.push((format!("backup/{}", filename), backup.clone()));
+            }
+        }
+        sources.into_iter().filter(|(_, it)| it.exists()).collect()
+    }
+
+    pub fn search(&self, re: &Regex, scope: SearchScope) -> Vec<SearchMatch> {
+        self.sources()
+            .into_iter()
+            .flat_map(|(source, source_path)| {
+                // The encrypted backups are skipped.
+                let content = fs::read_to_string(&source_path).unwrap();
+                if crypto::is_encrypted(&content) {
+                    return vec![];
+                }
+                dotenv::entries(&content)
+                    .into_iter()
+                    .filter(|it| scope.is_match(re, &it.key, &it.value))
+                    .map(|it| SearchMatch {
+                        source: source.clone(),
+                        line: it.line,
+                        key: it.key,
+                        value: it.value,
+                    })
+                    .collect::<Vec<SearchMatch>>()
+            })
+            .collect()
+    }
+}
+
+// The values which cannot be checked without decrypting or resolving them.
+fn is_opaque(value: &str) -> bool {
+    values::is_encrypted_value(value) || reference::is_reference(value)
+}
+
+// The variables the interpolated value of the key is built from with their values, so the
+// secrets in it can be redacted.
+fn references(
+    variables: &Configuration,
+    values: &HashMap<String, String>,
+    key: &str,
+) -> References {
+    interpolation::dependencies(variables, key)
+        .into_iter()
+        .map(|name| {
+            let value = values.get(&name).cloned().unwrap_or_default();
+            (name, value)
+        })
+        .collect()
+}
+
+// The key held by the agent if it's running, the agent is only a cache so the errors talking to it
+// are ignored and the key is read as usual.
+fn agent_key(name: &str) -> Option<String> {
+    Client::from_env().and_then(|it| it.get(name).ok().flatten())
+}
+
+fn add_agent_key(name: &str, value: &str) {
+    if let Some(client) = Client::from_env() {
+        let _ = client.set(name, value);
+    }
+}
+
+fn forget_agent_key(name: &str) {
+    if let Some(client) = Client::from_env() {
+        let _ = client.forget(name);
+    }
+}
+
+fn lookup_repository(dir: PathBuf) -> Option<PathBuf> {
+    dir.ancestors()
+        .take_while(|it| path::is_envm_repository(it))
+        .next()
+        .map(|it| it.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use std::error::Error;
+    use tempfile;
+
+    #[test]
+    fn not_a_repository() {
+        let root = Path::new("/not/a/repository").to_path_buf();
+        assert_eq!(lookup_repository(root), None);
+    }
+
+    #[test]
+    fn should_init_repo() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.into_path();
+        let repo = Repository::new(dir.clone());
+        repo.init()?;
+        assert!(path::get_envm_path(&repo.path).exists());
+        assert!(path::get_config_path(&repo.path).exists());
+        assert!(path::get_current_path(&repo.path).exists());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    fn create_envm_repo_use_local_env() -> Result<Repository, Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.into_path();
+        let repo = Repository::new(dir.clone());
+        repo.init()?;
+        Ok(repo)
+    }
+
+    // Rewrite the config with the default values and extra settings, then reload the repository.
+    fn configure_repo(repo: Repository, extra: &str) -> Result<Repository, Box<dyn Error>> {
+        let config = format!(
+            "local = \".env\"\npattern = \".env.{{}}\"\ntemplate = \".env.example\"\n{}\n",
+            extra
+        );
+        fs::write(path::get_config_path(&repo.path), config)?;
+        Ok(Repository::load(repo.path)?)
+    }
+
+    fn make_local_env_file(repo: &Repository) -> Result<PathBuf, Box<dyn Error>> {
+        let path = path::get_local_env_path(repo);
+        fs::write(&path, "ENV=local")?;
+        Ok(path)
+    }
+
+    fn make_env_file(repo: &Repository, env: &str) -> Result<PathBuf, Box<dyn Error>> {
+        let path = path::get_env_path(&repo, env);
+        let data = format!("ENV={}", env);
+        fs::write(&path, data)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn should_use_target_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let local_path = make_local_env_file(&repo)?;
+        make_env_file(&repo, "dev")?;
+
+        repo.use_environment("dev")?;
+
+        let head_path = path::get_current_path(&repo.path);
+        let head = fs::read_to_string(head_path)?;
+        assert_eq!(head, EnvType::Other(String::from("dev")).to_string());
+
+        let contents = fs::read_to_string(local_path)?;
+        assert_eq!(contents, "ENV=dev");
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_resolve_references_on_use() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let mut repo = configure_repo(repo, "[references]\ncommands = true\nfiles = true")?;
+        repo.permissions = Permissions {
+            commands: true,
+            files: true,
+        };
+        let local_path = make_local_env_file(&repo)?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "DB_PASSWORD=\nTOKEN=\n# @type=int\nPORT=\n")?;
+        let secret_path = repo.path.join("secret");
+        fs::write(&secret_path, "s3cret\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        let dev = format!(
+            "DB_PASSWORD=@file:{}\nTOKEN=@cmd:echo t0ken\nPORT=@cmd:echo 80\n",
+            secret_path.display()
+        );
+        fs::write(&dev_path, &dev)?;
+
+        assert!(repo.verify_environment("dev")?.is_clean());
+        repo.use_environment("dev")?;
+        assert_eq!(
+            fs::read_to_string(&local_path)?,
+            "DB_PASSWORD=s3cret\nTOKEN=t0ken\nPORT=80\n"
+        );
+        assert_eq!(fs::read_to_string(&dev_path)?, dev);
+        let shown: Vec<String> = repo
+            .show_environment("dev", false, false)?
+            .into_iter()
+            .map(|(_, value, _)| value)
+            .collect();
+        assert_eq!(shown, vec!["s3cret", "t0ken", "80"]);
+        let raw = repo.show_environment("dev", true, false)?;
+        assert_eq!(raw[1].1, "@cmd:echo t0ken");
+
+        fs::write(&dev_path, "TOKEN=@cmd:exit 1\n")?;
+        assert!(repo.use_environment("dev").is_err());
+        assert!(repo.show_environment("dev", false, false).is_err());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_backup_local_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_local_env_file(&repo)?;
+        make_env_file(&repo, "dev")?;
+
+        repo.use_environment("dev")?;
+        let backup_path = path::get_local_backup_path(&repo);
+        assert!(backup_path.exists());
+        let contents = fs::read_to_string(backup_path)?;
+        assert_eq!(contents, "ENV=local");
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_use_backup_local_env_use_local_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let local_path = make_local_env_file(&repo)?;
+        make_env_file(&repo, "dev")?;
+        repo.use_environment("dev")?;
+        let backup_path = path::get_local_backup_path(&repo);
+        fs::write(&backup_path, "ENV=backup")?;
+
+        // Sync repository state with file system
+        let repo = Repository::load(repo.path)?;
+        repo.use_environment("local")?;
+        let contents = fs::read_to_string(local_path)?;
+        assert_eq!(contents, "ENV=backup");
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    fn make_template_env_file(repo: &Repository) -> Result<PathBuf, Box<dyn Error>> {
+        let template_path = path::get_template_env_path(repo);
+        fs::write(&template_path, "ENV=")?;
+        Ok(template_path)
+    }
+
+    #[test]
+    fn should_new_target_env_file() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_template_env_file(&repo)?;
+
+        repo.new_environment("dev")?;
+
+        let dev_path = path::get_env_path(&repo, "dev");
+        assert!(dev_path.exists());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_new_local_env_file_by_config() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_template_env_file(&repo)?;
+
+        repo.new_environment("local")?;
+
+        let local_path = path::get_local_env_path(&repo);
+        assert!(local_path.exists());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_fill_defaults_in_new_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(
+            &template_path,
+            "# @default=3000\nPORT=\n# @default=x\nHOST=localhost\n",
+        )?;
+
+        repo.new_environment("dev")?;
+
+        let contents = fs::read_to_string(path::get_env_path(&repo, "dev"))?;
+        assert_eq!(
+            contents,
+            "# @default=3000\nPORT=3000\n# @default=x\nHOST=localhost\n"
+        );
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_verify_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let template_path = make_template_env_file(&repo)?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        fs::write(
+            &template_path,
+            "ENV=\n# @required\nAPP_URL=\n# @required @type=port\nPORT=\n",
+        )?;
+        fs::write(&dev_path, "APP_URL=\"\"\nPORT=abc\nDEBUG=true\n")?;
+
+        let verification = repo.verify_environment("dev")?;
+        assert_eq!(verification.missing, vec!["ENV"]);
+        assert_eq!(verification.extra, vec!["DEBUG"]);
+        assert_eq!(verification.empty_required, vec!["APP_URL"]);
+        assert_eq!(verification.file, ".env.dev");
+        assert_eq!(verification.invalid.len(), 1);
+        assert_eq!(verification.invalid[0].line, 2);
+        assert!(verification.placeholders.is_empty());
+        assert!(!verification.is_ok());
+
+        fs::write(&dev_path, "ENV=changeme\nAPP_URL=x\nPORT=80\n")?;
+        let verification = repo.verify_environment("dev")?;
+        assert_eq!(verification.placeholders, vec!["ENV"]);
+        assert!(verification.is_ok());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_verify_interpolated_values() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(
+            &template_path,
+            "HOST=\n# @type=url\nAPP_URL=\n# @type=url\nAPI_URL=\nA=\nB=\n",
+        )?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        fs::write(
+            &dev_path,
+            "HOST=example.com\nAPP_URL=https://${HOST}\nAPI_URL=${HOST}\nA=${B}\nB=${A}\n",
+        )?;
+
+        let verification = repo.verify_environment("dev")?;
+        let lines: Vec<(usize, &str)> = verification
+            .invalid
+            .iter()
+            .map(|it| (it.line, it.key.as_str()))
+            .collect();
+        assert_eq!(lines, vec![(3, "API_URL"), (5, "B")]);
+        assert_eq!(
+            verification.references("API_URL"),
+            [(String::from("HOST"), String::from("example.com"))]
+        );
+        assert_eq!(
+            verification.invalid[1].message,
+            "cyclic reference A -> B -> A"
+        );
+
+        fs::write(
+            &dev_path,
+            "HOST=example.com\nURL=https://${HOST}/\nRAW='${HOST}'\n",
+        )?;
+        assert_eq!(
+            repo.show_environment("dev", false, false)?,
+            vec![
+                (String::from("HOST"), String::from("example.com"), vec![]),
+                (
+                    String::from("URL"),
+                    String::from("https://example.com/"),
+                    vec![(String::from("HOST"), String::from("example.com"))]
+                ),
+                (String::from("RAW"), String::from("${HOST}"), vec![]),
+            ]
+        );
+        assert_eq!(
+            repo.show_environment("dev", true, false)?[1].1,
+            "https://${HOST}/"
+        );
+        fs::write(&dev_path, "URL=${MISSING}\n")?;
+        assert!(repo.show_environment("dev", false, false).is_err());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_verify_env_with_schema_file() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_template_env_file(&repo)?;
+        make_env_file(&repo, "dev")?;
+        make_env_file(&repo, "production")?;
+        let schema = r#"{
+            "properties": { "ENV": { "enum": ["dev", "staging"] } },
+            "x-envm-environments": { "production": { "required": ["SENTRY_DSN"] } }
+        }"#;
+        fs::write(path::get_schema_path(&repo), schema)?;
+
+        assert!(repo.verify_environment("dev")?.is_ok());
+        let verification = repo.verify_environment("production")?;
+        assert_eq!(verification.missing, vec!["SENTRY_DSN"]);
+        assert_eq!(verification.invalid[0].key, "ENV");
+
+        let exported = repo.export_schema()?;
+        assert!(exported.contains("\"enum\""));
+
+        let repo = configure_repo(repo, "schema = \"missing.json\"")?;
+        assert!(matches!(
+            repo.schema(),
+            Err(EnvmError::FailedToParseSchema(_))
+        ));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_check_env_before_use() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let repo = configure_repo(repo, "[environments.dev]\nstrictness = \"strict\"")?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "ENV=\n# @type=port\nPORT=\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        let production_path = make_env_file(&repo, "production")?;
+
+        fs::write(&production_path, "ENV=production\nPORT=abc\n")?;
+        let verification = repo.check_before_use("production")?.unwrap();
+        assert_eq!(verification.invalid[0].key, "PORT");
+
+        fs::write(&production_path, "ENV=production\nPORT=80\n")?;
+        assert!(repo.check_before_use("production")?.is_none());
+        // The extra variables are reported, but only refused by the strict environment.
+        fs::write(&production_path, "ENV=production\nPORT=80\nDEBUG=true\n")?;
+        let verification = repo.check_before_use("production")?.unwrap();
+        assert!(verification.is_acceptable(repo.strictness("production")));
+        fs::write(&dev_path, "ENV=dev\nPORT=80\nDEBUG=true\n")?;
+        let verification = repo.check_before_use("dev")?.unwrap();
+        assert!(!verification.is_acceptable(repo.strictness("dev")));
+        assert!(repo.check_before_use("local")?.is_none());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_lint_and_fix_files() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_template_env_file(&repo)?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        fs::write(&dev_path, "ENV = dev\nENV=dev2\n")?;
+
+        let results = repo.lint_files(false)?;
+        let rules: Vec<&str> = results
+            .iter()
+            .flat_map(|(_, problems)| problems.iter().map(|it| it.rule.name()))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                "missing-final-newline",
+                "space-around-equals",
+                "duplicate-key"
+            ]
+        );
+
+        let results = repo.lint_files(true)?;
+        assert_eq!(results[0], (String::from(".env.example"), vec![]));
+        assert_eq!(results[1].1.len(), 1);
+        assert_eq!(fs::read_to_string(&dev_path)?, "ENV=dev\nENV=dev2\n");
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_scan_secrets() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let repo = configure_repo(
+            repo,
+            "[environments.production]\ntier = \"production\"\n[environments.dev]\ntier = \"development\"",
+        )?;
+        fs::write(
+            path::get_env_path(&repo, "production"),
+            "API_TOKEN=prod-token-value\nPORT=80\n",
+        )?;
+        fs::write(
+            path::get_env_path(&repo, "dev"),
+            "API_TOKEN=prod-token-value\nPORT=80\n",
+        )?;
+        make_env_file(&repo, "qa")?;
+
+        let findings = repo.scan_secrets()?;
+        assert_eq!(findings.len(), 1);
+        assert!(
+            matches!(&findings[0], Finding::Reused { env, key, .. } if env == "dev" && key == "API_TOKEN")
+        );
+        assert!(path::get_salt_path(&repo).exists());
+        assert_eq!(repo.scan_secrets()?, findings);
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_encrypt_and_decrypt_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        *repo.passphrase.borrow_mut() = Some(String::from("correct horse"));
+        make_local_env_file(&repo)?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "ENV=\nPORT=\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        let encrypted_path = path::get_encrypted_env_path(&repo, "dev");
+
+        repo.encrypt_environment("dev", false)?;
+        assert!(!dev_path.exists());
+        assert!(crypto::is_encrypted(&fs::read_to_string(&encrypted_path)?));
+        assert_eq!(repo.list_environments(), vec![String::from("dev")]);
+        assert!(repo.encrypt_environment("dev", false).is_err());
+        assert!(repo.encrypt_environment("local", false).is_err());
+
+        assert_eq!(repo.sync_environment("dev", false)?, vec!["PORT="]);
+        assert!(!dev_path.exists());
+        assert_eq!(repo.read_environment("dev")?, "ENV=dev\nPORT=");
+        assert_eq!(repo.verify_environment("dev")?.file, ".env.dev.enc");
+
+        repo.use_environment("dev")?;
+        let local_path = path::get_local_env_path(&repo);
+        assert_eq!(fs::read_to_string(local_path)?, "ENV=dev\nPORT=");
+
+        fs::write(&dev_path, "ENV=other\n")?;
+        assert!(matches!(
+            repo.decrypt_environment("dev"),
+            Err(EnvmError::PlaintextEnvironmentExists(_))
+        ));
+        assert!(encrypted_path.exists());
+        fs::remove_file(&dev_path)?;
+        repo.decrypt_environment("dev")?;
+        assert!(!encrypted_path.exists());
+        assert_eq!(fs::read_to_string(&dev_path)?, "ENV=dev\nPORT=");
+        assert!(repo.decrypt_environment("dev").is_err());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_encrypt_env_values() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        *repo.passphrase.borrow_mut() = Some(String::from("correct horse"));
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "ENV=\n# @type=port\nPORT=\nDEBUG=\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        fs::write(&dev_path, "ENV=dev\nPORT=abc\n")?;
+
+        repo.encrypt_environment("dev", true)?;
+        let encrypted = fs::read_to_string(&dev_path)?;
+        assert!(repo.has_encrypted_values("dev"));
+        assert!(encrypted.starts_with("ENV=ENC["));
+        assert!(!encrypted.contains("dev\n"));
+        assert!(repo.encrypt_environment("dev", true).is_err());
+        assert_eq!(repo.list_environments(), vec![String::from("dev")]);
+
+        let verification = repo.verify_environment("dev")?;
+        assert_eq!(verification.missing, vec!["DEBUG"]);
+        assert_eq!(verification.file, ".env.dev");
+        assert!(verification.invalid.is_empty());
+
+        assert_eq!(repo.sync_environment("dev", false)?, vec!["DEBUG="]);
+        let synced = fs::read_to_string(&dev_path)?;
+        assert_eq!(synced.lines().next(), encrypted.lines().next());
+        assert_eq!(repo.read_environment("dev")?, "ENV=dev\nPORT=abc\nDEBUG=\n");
+
+        repo.decrypt_environment("dev")?;
+        assert!(!repo.has_encrypted_values("dev"));
+        assert_eq!(
+            fs::read_to_string(&dev_path)?,
+            "ENV=dev\nPORT=abc\nDEBUG=\n"
+        );
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_encrypt_env_to_recipients() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        *repo.passphrase.borrow_mut() = Some(String::from("correct horse"));
+        let alice = age::x25519::Identity::generate();
+        let bob = age::x25519::Identity::generate();
+        let alice_key = alice.to_public().to_string();
+        let bob_key = bob.to_public().to_string();
+        make_env_file(&repo, "dev")?;
+        repo.encrypt_environment("dev", false)?;
+        let encrypted_path = path::get_encrypted_env_path(&repo, "dev");
+
+        assert_eq!(repo.add_recipient(&alice_key)?, vec![String::from("dev")]);
+        assert!(!crypto::is_passphrase_encrypted(&fs::read_to_string(
+            &encrypted_path
+        )?));
+        *repo.identity.borrow_mut() = Some(alice.to_string().expose_secret().to_string());
+        repo.add_recipient(&bob_key)?;
+        assert_eq!(repo.recipients(), vec![alice_key.clone(), bob_key.clone()]);
+        assert!(repo.add_recipient(&bob_key).is_err());
+        assert!(repo.add_recipient("age1invalid").is_err());
+
+        let bob_identity = bob.to_string().expose_secret().to_string();
+        let encrypted = fs::read_to_string(&encrypted_path)?;
+        assert_eq!(
+            crypto::decrypt_with_identity(&encrypted, &bob_identity)?,
+            "ENV=dev"
+        );
+
+        repo.remove_recipient(&bob_key)?;
+        let encrypted = fs::read_to_string(&encrypted_path)?;
+        assert!(crypto::decrypt_with_identity(&encrypted, &bob_identity).is_err());
+        assert_eq!(repo.read_environment("dev")?, "ENV=dev");
+        assert!(repo.remove_recipient(&bob_key).is_err());
+        assert!(matches!(
+            repo.remove_recipient(&alice_key),
+            Err(EnvmError::MissingRecipients(_))
+        ));
+        assert_eq!(repo.recipients(), vec![alice_key]);
+        fs::write(path::get_recipients_path(&repo), "")?;
+        assert!(matches!(
+            repo.write_environment("dev", "ENV=qa"),
+            Err(EnvmError::MissingRecipients(_))
+        ));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_modified_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        *repo.signing_key.borrow_mut() = Some(b"key".to_vec());
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "ENV=\nPORT=\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        assert_eq!(repo.check_signature("dev")?, None);
+
+        assert_eq!(repo.sign_environments(&[])?, vec!["dev"]);
+        assert_eq!(repo.check_signature("dev")?, Some(Status::Valid));
+        repo.sync_environment("dev", false)?;
+        assert_eq!(repo.check_signature("dev")?, Some(Status::Valid));
+
+        fs::write(&dev_path, "ENV=staging\n")?;
+        assert!(matches!(
+            repo.sync_environment("dev", false),
+            Err(EnvmError::ModifiedEnvironment(_))
+        ));
+        assert_eq!(repo.check_signature("dev")?, Some(Status::Modified));
+        let verification = repo.verify_environment("dev")?;
+        assert!(verification.tampered);
+        assert!(!verification.is_ok());
+        assert!(repo.check_before_use("dev")?.is_some());
+
+        repo.sign_environments(&[String::from("dev")])?;
+        repo.sync_environment("dev", false)?;
+        assert!(repo.check_before_use("dev")?.is_none());
+        make_env_file(&repo, "qa")?;
+        assert_eq!(repo.check_signature("qa")?, Some(Status::Unsigned));
+
+        *repo.signing_key.borrow_mut() = Some(b"other".to_vec());
+        assert!(repo.check_signature("dev").is_err());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_fingerprint_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_env_file(&repo, "dev")?;
+
+        let fingerprint = repo.fingerprint("dev", None)?;
+        let other = repo.fingerprint("dev", Some(fingerprint.salt()))?;
+        assert_eq!(fingerprint, other);
+        assert!(repo.fingerprint("qa", None).is_err());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_append_audit_log() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+
+        repo.audit("reveal grep")?;
+        repo.audit("reveal verify")?;
+
+        let log = fs::read_to_string(path::get_audit_log_path(&repo))?;
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" reveal grep"));
+        assert!(lines[1].ends_with(" reveal verify"));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_format_files() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "ENV=\nPORT=\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        fs::write(&dev_path, "PORT = '80'\nENV=dev\n")?;
+
+        let changed = repo.format_files(&[], false, true)?;
+        assert_eq!(changed, vec![String::from(".env.dev")]);
+        assert_eq!(fs::read_to_string(&dev_path)?, "PORT = '80'\nENV=dev\n");
+
+        repo.format_files(&[String::from("dev")], true, false)?;
+        assert_eq!(fs::read_to_string(&dev_path)?, "ENV=dev\nPORT=80\n");
+        assert!(repo.format_files(&[], true, true)?.is_empty());
+        assert!(repo
+            .format_files(&[String::from("qa")], false, true)
+            .is_err());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_list_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_template_env_file(&repo)?;
+        make_env_file(&repo, "dev")?;
+        make_env_file(&repo, "production")?;
+
+        assert_eq!(
+            repo.list_environments().sort(),
+            vec!["dev", "production"].sort()
+        );
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_remove_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_template_env_file(&repo)?;
+        make_env_file(&repo, "dev")?;
+
+        repo.remove_environment("dev")?;
+        let dev_path = path::get_env_path(&repo, "dev");
+        assert!(!dev_path.exists());
+
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_remove_current_env() -> Result<(), Box<dyn Error>> {
         let repo = create_envm_repo_use_local_env()?;
 
         match repo.remove_environment("local") {
@@ -366,4 +2170,354 @@ mod tests {
         fs::remove_dir_all(repo.path)?;
         Ok(())
     }
+
+    #[test]
+    fn should_sync_env_with_template() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "ENV=\n# the port\nPORT=3000\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+
+        assert_eq!(repo.sync_environment("dev", true)?, vec!["PORT=3000"]);
+        assert_eq!(fs::read_to_string(&dev_path)?, "ENV=dev");
+
+        assert_eq!(repo.sync_environment("dev", false)?, vec!["PORT=3000"]);
+        assert_eq!(
+            fs::read_to_string(&dev_path)?,
+            "ENV=dev\n# the port\nPORT=3000"
+        );
+        assert!(repo.sync_environment("dev", false)?.is_empty());
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_compose_included_files() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let template_path = make_template_env_file(&repo)?;
+        fs::write(&template_path, "ENV=\nPORT=\nREGION=\n")?;
+        make_local_env_file(&repo)?;
+        fs::write(repo.path.join(".env.shared"), "REGION=eu\nPORT=80\n")?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        fs::write(&dev_path, "#@include .env.shared\nENV=dev\nPORT=3000\n")?;
+
+        assert!(repo.sync_environment("dev", false)?.is_empty());
+        let verification = repo.verify_environment("dev")?;
+        assert!(verification.is_clean());
+        assert_eq!(verification.source("REGION"), ".env.shared");
+        assert_eq!(verification.source("PORT"), ".env.dev");
+
+        repo.use_environment("dev")?;
+        assert_eq!(
+            fs::read_to_string(path::get_local_env_path(&repo))?,
+            "REGION=eu\nENV=dev\nPORT=3000\n"
+        );
+
+        fs::write(repo.path.join(".env.shared"), "#@include .env.dev\n")?;
+        assert!(matches!(
+            repo.verify_environment("dev"),
+            Err(EnvmError::FailedToInclude(..))
+        ));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_sign_included_and_parent_files() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let repo = configure_repo(repo, "[environments.dev]\nparent = \"base\"")?;
+        *repo.signing_key.borrow_mut() = Some(b"key".to_vec());
+        fs::create_dir(repo.path.join("shared"))?;
+        let shared_path = repo.path.join("shared/common.env");
+        fs::write(&shared_path, "REGION=eu\n")?;
+        let base_path = make_env_file(&repo, "base")?;
+        fs::write(&base_path, "#@include shared/common.env\nPORT=80\n")?;
+        make_env_file(&repo, "dev")?;
+
+        repo.sign_environments(&[String::from("dev")])?;
+        assert_eq!(repo.check_signatures("dev")?, Some(Status::Valid));
+
+        fs::write(&shared_path, "REGION=us\n")?;
+        assert_eq!(repo.check_signature("dev")?, Some(Status::Valid));
+        assert_eq!(repo.check_signatures("dev")?, Some(Status::Modified));
+        assert!(repo.check_before_use("dev")?.unwrap().tampered);
+
+        repo.sign_environments(&[String::from("dev")])?;
+        fs::write(&base_path, "PORT=81\n")?;
+        assert_eq!(repo.check_signatures("dev")?, Some(Status::Modified));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_inherit_parent_env() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let repo = configure_repo(
+            repo,
+            "[environments.production]\nparent = \"base\"\n[environments.base]\nparent = \"common\"",
+        )?;
+        make_local_env_file(&repo)?;
+        fs::write(make_env_file(&repo, "common")?, "REGION=eu\nPORT=80\n")?;
+        fs::write(make_env_file(&repo, "base")?, "PORT=8080\nLOG=info\n")?;
+        fs::write(make_env_file(&repo, "production")?, "LOG=warn\n")?;
+
+        assert_eq!(
+            repo.ancestry("production")?,
+            vec!["common", "base", "production"]
+        );
+        let files: Vec<String> = repo
+            .explain("production", "PORT")?
+            .into_iter()
+            .map(|it| format!("{}:{}={}", it.file, it.line, it.value))
+            .collect();
+        assert_eq!(files, vec![".env.common:2=80", ".env.base:1=8080"]);
+        assert!(matches!(
+            repo.explain("production", "MISSING"),
+            Err(EnvmError::MissingVariable(..))
+        ));
+
+        repo.use_environment("production")?;
+        assert_eq!(
+            fs::read_to_string(path::get_local_env_path(&repo))?,
+            "REGION=eu\nPORT=8080\nLOG=warn\n"
+        );
+
+        let repo = configure_repo(
+            repo,
+            "[environments.common]\nparent = \"base\"\n[environments.base]\nparent = \"common\"",
+        )?;
+        assert!(matches!(
+            repo.ancestry("base"),
+            Err(EnvmError::CyclicParentEnvironments(_))
+        ));
+        let repo = configure_repo(repo, "[environments.dev]\nparent = \"missing\"")?;
+        fs::write(make_env_file(&repo, "dev")?, "LOG=debug\n")?;
+        assert!(matches!(
+            repo.compose_environment("dev"),
+            Err(EnvmError::MissingParentEnvironment(..))
+        ));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_use_stacked_envs() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let repo = configure_repo(repo, "[environments.debug]\nstrictness = \"strict\"")?;
+        make_local_env_file(&repo)?;
+        fs::write(
+            make_env_file(&repo, "staging")?,
+            "ENV=staging\nPAYMENTS=off\n",
+        )?;
+        fs::write(make_env_file(&repo, "payments")?, "PAYMENTS=on\n")?;
+        fs::write(make_env_file(&repo, "debug")?, "DEBUG=true\n")?;
+
+        assert_eq!(repo.strictness("staging+debug"), Strictness::Strict);
+        repo.use_environment("staging+payments+debug")?;
+        assert_eq!(
+            fs::read_to_string(path::get_local_env_path(&repo))?,
+            "ENV=staging\nPAYMENTS=on\nDEBUG=true\n"
+        );
+        let head = fs::read_to_string(path::get_current_path(&repo.path))?;
+        assert_eq!(head, "staging+payments+debug");
+
+        let repo = Repository::load(repo.path)?;
+        assert!(matches!(
+            repo.remove_environment("payments"),
+            Err(EnvmError::RemovingUsingEnvironment(_))
+        ));
+        assert!(matches!(
+            repo.use_environment("staging + payments + debug"),
+            Err(EnvmError::AlreadyUsingTargetEnvironment(_))
+        ));
+        assert!(matches!(
+            repo.use_environment("local+debug"),
+            Err(EnvmError::InvalidEnvironmentStack(..))
+        ));
+        assert!(matches!(
+            repo.new_environment("a+b"),
+            Err(EnvmError::InvalidEnvironmentStack(..))
+        ));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_not_sync_overlays_by_default() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let repo = configure_repo(repo, "[environments.debug]\noverlay = true")?;
+        make_local_env_file(&repo)?;
+        fs::write(make_template_env_file(&repo)?, "ENV=\nLOG_LEVEL=info\n")?;
+        fs::write(make_env_file(&repo, "staging")?, "ENV=staging\n")?;
+        let debug_path = make_env_file(&repo, "debug")?;
+        fs::write(&debug_path, "LOG_LEVEL=debug\n")?;
+
+        assert_eq!(repo.default_environments(), vec!["staging"]);
+        for env in repo.default_environments() {
+            repo.sync_environment(&env, false)?;
+        }
+        assert_eq!(fs::read_to_string(&debug_path)?, "LOG_LEVEL=debug\n");
+        repo.use_environment("staging+debug")?;
+        assert_eq!(
+            fs::read_to_string(path::get_local_env_path(&repo))?,
+            "ENV=staging\nLOG_LEVEL=debug\n"
+        );
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_use_stage_of_every_service() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let config = "local = \".env.{service}\"\npattern = \".env.{service}.{stage}\"\ntemplate = \".env.example\"\n";
+        fs::write(path::get_config_path(&repo.path), config)?;
+        let repo = Repository::load(repo.path)?;
+        for env in ["api.dev", "api.staging", "worker.staging"] {
+            fs::write(
+                repo.path.join(format!(".env.{}", env)),
+                format!("ENV={}\n", env),
+            )?;
+        }
+
+        assert_eq!(
+            repo.list_environments(),
+            vec!["api.dev", "api.staging", "worker.staging"]
+        );
+        let selection = repo.stage_selection("staging")?;
+        assert_eq!(selection, "*.staging");
+        assert_eq!(
+            repo.select(&selection),
+            vec!["api.staging", "worker.staging"]
+        );
+
+        repo.use_environment(&selection)?;
+        assert_eq!(
+            fs::read_to_string(repo.path.join(".env.api"))?,
+            "ENV=api.staging\n"
+        );
+        assert_eq!(
+            fs::read_to_string(repo.path.join(".env.worker"))?,
+            "ENV=worker.staging\n"
+        );
+        let repo = Repository::load(repo.path)?;
+        assert!(repo.current_env().is_using("worker.staging"));
+        assert!(matches!(
+            repo.remove_environment("api.staging"),
+            Err(EnvmError::RemovingUsingEnvironment(_))
+        ));
+        for env in ["api", "api.", "api.dev.x"] {
+            assert!(matches!(
+                repo.new_environment(env),
+                Err(EnvmError::InvalidEnvironmentName(..))
+            ));
+        }
+        for env in ["api", "api.dev.x", "api.dev+worker"] {
+            assert!(matches!(
+                repo.use_environment(env),
+                Err(EnvmError::InvalidEnvironmentName(..))
+            ));
+        }
+        assert!(matches!(
+            repo.remove_environment("worker"),
+            Err(EnvmError::InvalidEnvironmentName(..))
+        ));
+
+        let config =
+            "local = \".env\"\npattern = \".env.{service}.{stage}\"\ntemplate = \".env.example\"\n";
+        fs::write(path::get_config_path(&repo.path), config)?;
+        let repo = Repository::load(repo.path)?;
+        repo.use_environment("api.dev")?;
+        let repo = Repository::load(repo.path)?;
+        assert!(matches!(
+            repo.use_environment("*.staging"),
+            Err(EnvmError::SharedLocalEnvironment(_))
+        ));
+        let repo = configure_repo(repo, "")?;
+        assert!(matches!(
+            repo.stage_selection("staging"),
+            Err(EnvmError::MissingDimension(_))
+        ));
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_prune_extra_variables() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        let repo = configure_repo(repo, r#"local_only = ["DEBUG_SQL"]"#)?;
+        make_template_env_file(&repo)?;
+        let dev_path = make_env_file(&repo, "dev")?;
+        fs::write(&dev_path, "ENV=dev\n# retired\nOLD=1\nDEBUG_SQL=true\n")?;
+        fs::write(&dev_path, "OLD=0\nNEW=1\nOLD=2\n")?;
+        assert_eq!(repo.prune_environment("dev", true)?, vec!["OLD", "NEW"]);
+        fs::write(&dev_path, "ENV=dev\n# retired\nOLD=1\nDEBUG_SQL=true\n")?;
+
+        assert_eq!(repo.prune_environment("dev", true)?, vec!["OLD"]);
+        assert!(!path::get_backups_path(&repo).exists());
+
+        assert_eq!(repo.prune_environment("dev", false)?, vec!["OLD"]);
+        assert_eq!(fs::read_to_string(&dev_path)?, "ENV=dev\nDEBUG_SQL=true\n");
+        let backups: Vec<_> = fs::read_dir(path::get_backups_path(&repo))?.collect();
+        assert_eq!(backups.len(), 1);
+        let backup = fs::read_to_string(backups[0].as_ref().unwrap().path())?;
+        assert_eq!(backup, "ENV=dev\n# retired\nOLD=1\nDEBUG_SQL=true\n");
+
+        fs::write(&dev_path, "# --- Legacy ---\nOLD=1\nENV=dev\n")?;
+        assert_eq!(repo.prune_environment("dev", false)?, vec!["OLD"]);
+        assert_eq!(
+            fs::read_to_string(&dev_path)?,
+            "# --- Legacy ---\nENV=dev\n"
+        );
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_generate_template_from_envs() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_env_file(&repo, "dev")?;
+        let production_path = make_env_file(&repo, "production")?;
+        fs::write(&production_path, "ENV=production\nSECRET=xyz\n")?;
+
+        assert_eq!(repo.generate_template(false, "")?, vec!["ENV=", "SECRET="]);
+        let template_path = path::get_template_env_path(&repo);
+        assert_eq!(fs::read_to_string(&template_path)?, "ENV=\nSECRET=\n");
+        assert!(matches!(
+            repo.generate_template(false, ""),
+            Err(EnvmError::TemplateEnvironmentAlreadyExists(_))
+        ));
+
+        fs::write(&production_path, "ENV=production\nSECRET=xyz\nPORT=80\n")?;
+        assert_eq!(
+            repo.generate_template(true, "changeme")?,
+            vec!["PORT=changeme"]
+        );
+        assert_eq!(
+            fs::read_to_string(&template_path)?,
+            "ENV=\nSECRET=\nPORT=changeme\n"
+        );
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_search_across_environments() -> Result<(), Box<dyn Error>> {
+        let repo = create_envm_repo_use_local_env()?;
+        make_template_env_file(&repo)?;
+        make_local_env_file(&repo)?;
+        make_env_file(&repo, "dev")?;
+        make_env_file(&repo, "production")?;
+
+        let re = Regex::new("^(dev|local)$")?;
+        let matches = repo.search(&re, SearchScope::Values);
+        let sources: Vec<&str> = matches.iter().map(|it| it.source.as_str()).collect();
+        assert_eq!(sources, vec!["local", "dev"]);
+        assert!(matches.iter().all(|it| it.line == 1 && it.key == "ENV"));
+
+        let re = Regex::new("ENV")?;
+        assert!(repo.search(&re, SearchScope::Values).is_empty());
+        assert_eq!(repo.search(&re, SearchScope::Keys).len(), 4);
+        fs::remove_dir_all(repo.path)?;
+        Ok(())
+    }
 }
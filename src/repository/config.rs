@@ -1,13 +1,93 @@
 use confy;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use crate::error::EnvmError;
+use crate::lint::LintConfig;
+use crate::reference::ReferenceConfig;
 
 #[derive(Deserialize, Serialize, Debug, Default)]
 pub struct Config {
     local: String,
     pattern: String,
     template: String,
+    // The variables only exist in some environments on purpose, prune won't remove them.
+    #[serde(default)]
+    local_only: Vec<String>,
+    // The path of the JSON Schema describing the variables, default to `.envm/schema.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema: Option<String>,
+    // The regular expressions of the values which are placeholders rather than real values.
+    #[serde(default = "default_placeholders")]
+    placeholders: Vec<String>,
+    // Resolve the `${VAR}` references missing from the file from the process environment.
+    #[serde(default)]
+    interpolate_from_env: bool,
+    // The globs of the keys whose values are redacted in the output.
+    #[serde(default = "default_secrets")]
+    secrets: Vec<String>,
+    #[serde(default, skip_serializing_if = "LintConfig::is_empty")]
+    lint: LintConfig,
+    #[serde(default, skip_serializing_if = "ReferenceConfig::is_default")]
+    references: ReferenceConfig,
+    // The settings of the specific environment, keyed by the environment name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    environments: HashMap<String, EnvironmentConfig>,
+}
+
+fn default_placeholders() -> Vec<String> {
+    vec![
+        String::from("(?i)^(changeme|change-me|change_me|todo|tbd|fixme|placeholder|x{3,})$"),
+        String::from("(?i)^your[-_].*[-_]here$"),
+        String::from("^<.+>$"),
+    ]
+}
+
+fn default_secrets() -> Vec<String> {
+    vec![
+        String::from("*_SECRET"),
+        String::from("*_TOKEN"),
+        String::from("*_PASSWORD"),
+        String::from("*_API_KEY"),
+        String::from("*_PRIVATE_KEY"),
+    ]
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct EnvironmentConfig {
+    #[serde(default)]
+    pub strictness: Strictness,
+    // Used by the secret scan, unmarked environments are treated as non-production.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<Tier>,
+    // The environment to inherit the variables from, so the file only keeps what differs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    // Only stacked on top of the other environments, like `debug`, so it keeps a few variables
+    // and is not synced or verified unless named.
+    #[serde(default)]
+    pub overlay: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    Production,
+    Development,
+}
+
+// How strict the environment is checked before using it.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Strictness {
+    // Only warn about the problems.
+    Lax,
+    // Refuse the missing variables, empty required variables and invalid values.
+    #[default]
+    Normal,
+    // Refuse the extra variables as well.
+    Strict,
 }
 
 impl Config {
@@ -16,6 +96,14 @@ impl Config {
             local: String::from(".env"),
             pattern: String::from(".env.{}"),
             template: String::from(".env.example"),
+            local_only: vec![],
+            schema: None,
+            placeholders: default_placeholders(),
+            interpolate_from_env: false,
+            secrets: default_secrets(),
+            lint: LintConfig::default(),
+            references: ReferenceConfig::default(),
+            environments: HashMap::new(),
         }
     }
 
@@ -32,18 +120,88 @@ impl Config {
         &self.pattern
     }
 
+    // The named placeholders of the pattern in order, like `service` and `stage` of
+    // `.env.{service}.{stage}`, which is empty for the single `{}`.
+    pub fn dimensions(&self) -> Vec<String> {
+        placeholders(&self.pattern)
+    }
+
+    // The named placeholders of the local, so each service can have its own local file like
+    // `.env.{service}`.
+    pub fn local_dimensions(&self) -> Vec<String> {
+        placeholders(&self.local)
+    }
+
     pub fn template(&self) -> &String {
         &self.template
     }
 
+    pub fn local_only(&self) -> &Vec<String> {
+        &self.local_only
+    }
+
+    pub fn schema(&self) -> Option<&String> {
+        self.schema.as_ref()
+    }
+
+    pub fn placeholders(&self) -> &Vec<String> {
+        &self.placeholders
+    }
+
+    pub fn interpolate_from_env(&self) -> bool {
+        self.interpolate_from_env
+    }
+
+    pub fn secrets(&self) -> &Vec<String> {
+        &self.secrets
+    }
+
+    pub fn lint(&self) -> &LintConfig {
+        &self.lint
+    }
+
+    pub fn references(&self) -> &ReferenceConfig {
+        &self.references
+    }
+
+    pub fn environment(&self, env: &str) -> Option<&EnvironmentConfig> {
+        self.environments.get(env)
+    }
+
+    pub fn strictness(&self, env: &str) -> Strictness {
+        self.environment(env)
+            .map(|it| it.strictness)
+            .unwrap_or_default()
+    }
+
+    pub fn tier(&self, env: &str) -> Option<Tier> {
+        self.environment(env).and_then(|it| it.tier)
+    }
+
+    pub fn parent(&self, env: &str) -> Option<&String> {
+        self.environment(env).and_then(|it| it.parent.as_ref())
+    }
+
+    pub fn is_overlay(&self, env: &str) -> bool {
+        self.environment(env).is_some_and(|it| it.overlay)
+    }
+
     pub fn store(&self, path: &str) {
         confy::store_path(path, self).unwrap();
     }
 }
 
+fn placeholders(text: &str) -> Vec<String> {
+    let re = Regex::new(r"\{([a-z_]+)\}").unwrap();
+    re.captures_iter(text)
+        .map(|it| String::from(&it[1]))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lint::{Rule, Severity};
     use std::io::prelude::*;
     use tempfile::NamedTempFile;
 
@@ -60,6 +218,87 @@ mod tests {
         assert_eq!(config.local, ".env");
         assert_eq!(config.pattern, ".env.{}");
         assert_eq!(config.template, ".env.example");
+        assert!(config.local_only.is_empty());
+        assert!(config.schema.is_none());
+        assert_eq!(config.placeholders, default_placeholders());
+        assert_eq!(config.secrets, default_secrets());
+        Ok(())
+    }
+
+    #[test]
+    fn should_get_dimensions() {
+        let mut config = Config::new();
+        assert!(config.dimensions().is_empty());
+        config.pattern = String::from(".env.{service}.{stage}");
+        config.local = String::from(".env.{service}");
+        assert_eq!(config.dimensions(), vec!["service", "stage"]);
+        assert_eq!(config.local_dimensions(), vec!["service"]);
+    }
+
+    #[test]
+    fn prase_config_with_local_only() -> Result<(), EnvmError> {
+        let mut file = NamedTempFile::new().unwrap();
+        let content = r#"
+            local = ".env"
+            pattern = ".env.{}"
+            template = ".env.example"
+            local_only = ["DEBUG_SQL"]
+        "#;
+        file.write_all(content.as_bytes()).unwrap();
+        let config = Config::load(file.path().to_str().unwrap())?;
+        assert_eq!(config.local_only, vec!["DEBUG_SQL"]);
+        Ok(())
+    }
+
+    #[test]
+    fn prase_config_with_environments() -> Result<(), EnvmError> {
+        let mut file = NamedTempFile::new().unwrap();
+        let content = r#"
+            local = ".env"
+            pattern = ".env.{}"
+            template = ".env.example"
+
+            [environments.production]
+            strictness = "strict"
+            tier = "production"
+            parent = "base"
+
+            [environments.dev]
+            strictness = "lax"
+            tier = "development"
+        "#;
+        file.write_all(content.as_bytes()).unwrap();
+        let config = Config::load(file.path().to_str().unwrap())?;
+        assert_eq!(config.strictness("production"), Strictness::Strict);
+        assert_eq!(config.strictness("dev"), Strictness::Lax);
+        assert_eq!(config.strictness("staging"), Strictness::Normal);
+        assert_eq!(config.tier("production"), Some(Tier::Production));
+        assert_eq!(config.tier("dev"), Some(Tier::Development));
+        assert_eq!(config.tier("staging"), None);
+        assert_eq!(config.parent("production"), Some(&String::from("base")));
+        assert_eq!(config.parent("dev"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn prase_config_with_lint() -> Result<(), EnvmError> {
+        let mut file = NamedTempFile::new().unwrap();
+        let content = r#"
+            local = ".env"
+            pattern = ".env.{}"
+            template = ".env.example"
+
+            [lint]
+            allow = ["npm_config_cache"]
+
+            [lint.severity]
+            lowercase-key = "off"
+        "#;
+        file.write_all(content.as_bytes()).unwrap();
+        let config = Config::load(file.path().to_str().unwrap())?;
+        assert_eq!(config.lint.allow, vec!["npm_config_cache"]);
+        assert_eq!(config.lint.severity(Rule::LowercaseKey), Severity::Off);
+        assert_eq!(config.lint.severity(Rule::DuplicateKey), Severity::Error);
         Ok(())
     }
 }
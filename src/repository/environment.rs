@@ -1,10 +1,18 @@
+use std::collections::BTreeSet;
+
 // To identify the current environment is local or other, since in most case we won't have another
 // file for local environment, so we need to backup the environment file if we are using local
 // environment configuration, then we can switch back to local environment later.
+//
+// The environments can be stacked as `staging+feature-payments+debug`, which are merged from left
+// to right when using them. With the named placeholders in pattern, `*.staging` selects the
+// staging environment of every service, each is used at its own local file.
 #[derive(Debug)]
 pub enum EnvType {
     Local,
     Other(String),
+    Stack(String),
+    Selection(String),
 }
 
 impl EnvType {
@@ -12,6 +20,15 @@ impl EnvType {
         let contents = contents.trim();
         if contents == "local" {
             EnvType::Local
+        } else if contents.contains('+') {
+            let layers: Vec<&str> = contents
+                .split('+')
+                .map(str::trim)
+                .filter(|it| !it.is_empty())
+                .collect();
+            EnvType::Stack(layers.join("+"))
+        } else if contents.contains('*') {
+            EnvType::Selection(String::from(contents))
         } else {
             EnvType::Other(String::from(contents))
         }
@@ -20,7 +37,34 @@ impl EnvType {
     pub fn to_string(&self) -> &str {
         match &self {
             EnvType::Local => "local",
-            EnvType::Other(value) => &value,
+            EnvType::Other(value) => value,
+            EnvType::Stack(value) => value,
+            EnvType::Selection(value) => value,
+        }
+    }
+
+    // The environments to merge in order, which is the environment itself unless it's a stack.
+    pub fn layers(&self) -> Vec<&str> {
+        match &self {
+            EnvType::Stack(value) => value.split('+').collect(),
+            _ => vec![self.to_string()],
+        }
+    }
+
+    // Whether the environment is used by this one, including the layers of the stack and the
+    // environments matching the selection.
+    pub fn is_using(&self, env: &str) -> bool {
+        match &self {
+            EnvType::Selection(value) => {
+                let values: Vec<&str> = env.split('.').collect();
+                let selection: Vec<&str> = value.split('.').collect();
+                values.len() == selection.len()
+                    && selection
+                        .iter()
+                        .zip(values)
+                        .all(|(selected, it)| *selected == "*" || *selected == it)
+            }
+            _ => self.layers().contains(&env),
         }
     }
 
@@ -28,11 +72,72 @@ impl EnvType {
         match (self, target) {
             (EnvType::Local, EnvType::Local) => true,
             (EnvType::Other(self_env), EnvType::Other(target_env)) => self_env == target_env,
+            (EnvType::Stack(self_env), EnvType::Stack(target_env)) => self_env == target_env,
+            (EnvType::Selection(self_env), EnvType::Selection(target_env)) => {
+                self_env == target_env
+            }
             _ => false,
         }
     }
 }
 
+// The environments of the named placeholders as a table, the columns are the values of `stage`,
+// or the last placeholder if there is no `stage`, and the rows are the values of the others.
+#[derive(Debug, PartialEq)]
+pub struct Matrix {
+    // The placeholders of the rows joined by `.`, and the placeholder of the columns.
+    pub rows_dimension: String,
+    pub columns_dimension: String,
+    pub columns: Vec<String>,
+    // Whether the environment exists in each column.
+    pub rows: Vec<(String, Vec<bool>)>,
+}
+
+impl Matrix {
+    pub fn new(dimensions: &[String], envs: &[String]) -> Matrix {
+        let column = dimensions
+            .iter()
+            .position(|it| it == "stage")
+            .unwrap_or(dimensions.len().saturating_sub(1));
+        let split = |env: &String| {
+            let mut values: Vec<String> = env
+                .splitn(dimensions.len(), '.')
+                .map(String::from)
+                .collect();
+            let value = if column < values.len() {
+                values.remove(column)
+            } else {
+                String::new()
+            };
+            (values.join("."), value)
+        };
+        let cells: BTreeSet<(String, String)> = envs.iter().map(split).collect();
+        let columns: BTreeSet<&String> = cells.iter().map(|(_, it)| it).collect();
+        let rows: BTreeSet<&String> = cells.iter().map(|(it, _)| it).collect();
+        let mut others = dimensions.to_vec();
+        let columns_dimension = if column < others.len() {
+            others.remove(column)
+        } else {
+            String::new()
+        };
+        Matrix {
+            rows_dimension: others.join("."),
+            columns_dimension,
+            columns: columns.iter().map(|it| it.to_string()).collect(),
+            rows: rows
+                .iter()
+                .map(|row| {
+                    let exists = columns
+                        .iter()
+                        .map(|column| cells.contains(&(row.to_string(), column.to_string())))
+                        .collect();
+                    (row.to_string(), exists)
+                })
+                .collect(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -49,10 +154,53 @@ mod tests {
         assert!(matches!(env, EnvType::Other(v) if v == "dev"));
     }
 
+    #[test]
+    fn get_stack_head() {
+        let env = EnvType::from("staging + feature-payments+debug\n");
+        assert!(matches!(&env, EnvType::Stack(v) if v == "staging+feature-payments+debug"));
+        assert_eq!(env.layers(), vec!["staging", "feature-payments", "debug"]);
+        assert!(env.is_using("debug"));
+        assert!(!env.is_using("feature"));
+        assert_eq!(EnvType::from("dev").layers(), vec!["dev"]);
+    }
+
+    #[test]
+    fn get_selection_head() {
+        let env = EnvType::from("*.staging");
+        assert!(matches!(&env, EnvType::Selection(v) if v == "*.staging"));
+        assert!(env.is_using("api.staging"));
+        assert!(!env.is_using("api.production"));
+        assert!(!env.is_using("staging"));
+    }
+
+    #[test]
+    fn should_build_matrix() {
+        let dimensions = vec![String::from("stage"), String::from("service")];
+        let envs = vec![
+            String::from("dev.api"),
+            String::from("staging.api"),
+            String::from("staging.worker"),
+        ];
+
+        let matrix = Matrix::new(&dimensions, &envs);
+
+        assert_eq!(matrix.rows_dimension, "service");
+        assert_eq!(matrix.columns_dimension, "stage");
+        assert_eq!(matrix.columns, vec!["dev", "staging"]);
+        assert_eq!(
+            matrix.rows,
+            vec![
+                (String::from("api"), vec![true, true]),
+                (String::from("worker"), vec![false, true]),
+            ]
+        );
+    }
+
     #[test]
     fn should_equal() {
         assert!(EnvType::from("local").is_equal(&EnvType::from("local")));
         assert!(EnvType::from("dev").is_equal(&EnvType::from("dev")));
+        assert!(EnvType::from("dev+debug").is_equal(&EnvType::from("dev + debug")));
     }
 
     #[test]
@@ -60,5 +208,6 @@ mod tests {
         assert!(!EnvType::from("local").is_equal(&EnvType::from("dev")));
         assert!(!EnvType::from("dev").is_equal(&EnvType::from("local")));
         assert!(!EnvType::from("dev").is_equal(&EnvType::from("production")));
+        assert!(!EnvType::from("dev+debug").is_equal(&EnvType::from("debug+dev")));
     }
 }
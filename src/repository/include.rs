@@ -0,0 +1,338 @@
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+use crate::configuration::parser::dotenv::{self, Entry};
+use crate::configuration::Configuration;
+
+// A line of the composed environment with the file and the line number (start from 1) it comes
+// from.
+#[derive(Debug, Clone, PartialEq)]
+struct Line {
+    text: String,
+    file: String,
+    line: usize,
+}
+
+// A definition of the variable, with the file and the line number it comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub file: String,
+    pub line: usize,
+    pub value: String,
+}
+
+// The environment with the included files expanded in place, a variable defined again later
+// overrides the earlier one, which is removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Composition {
+    file: String,
+    // All lines including the overridden definitions.
+    lines: Vec<Line>,
+    // The included files in the order they are read, even the ones without any line.
+    includes: Vec<String>,
+    trailing_newline: bool,
+}
+
+impl Composition {
+    // Merge the compositions in order, the later ones override the earlier ones. It's the file
+    // of the last composition, like the environment inheriting from its parents.
+    pub fn merge(compositions: Vec<Composition>) -> Composition {
+        let last = compositions.last().unwrap();
+        let file = last.file.clone();
+        let trailing_newline = last.trailing_newline;
+        let mut includes: Vec<String> = vec![];
+        for it in compositions.iter().flat_map(|it| &it.includes) {
+            if !includes.contains(it) {
+                includes.push(it.clone());
+            }
+        }
+        Composition {
+            file,
+            lines: compositions.into_iter().flat_map(|it| it.lines).collect(),
+            includes,
+            trailing_newline,
+        }
+    }
+
+    // The files included by the environments, relative to the repository.
+    pub fn includes(&self) -> &[String] {
+        &self.includes
+    }
+
+    // Whether any line comes from the other files, the content is the same as the file
+    // otherwise.
+    pub fn is_composed(&self) -> bool {
+        self.lines.iter().any(|it| it.file != self.file)
+    }
+
+    // Every definition of the variable in order, the last one is the final value.
+    pub fn definitions(&self, key: &str) -> Vec<Definition> {
+        self.lines
+            .iter()
+            .filter_map(|it| match dotenv::parse_line(&it.text) {
+                Some((name, value)) if name == key => Some(Definition {
+                    file: it.file.clone(),
+                    line: it.line,
+                    value,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn content(&self) -> String {
+        let mut content = self
+            .effective_lines()
+            .iter()
+            .map(|it| it.text.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        if self.trailing_newline && !content.is_empty() {
+            content.push('\n');
+        }
+        content
+    }
+
+    pub fn variables(&self) -> Configuration {
+        dotenv::parse(&self.content())
+    }
+
+    // The variables with the line numbers in the files they come from.
+    pub fn entries(&self) -> Vec<Entry> {
+        self.effective_lines()
+            .iter()
+            .filter_map(|it| {
+                dotenv::parse_line(&it.text).map(|(key, value)| Entry {
+                    line: it.line,
+                    key,
+                    value,
+                })
+            })
+            .collect()
+    }
+
+    // The file of each variable which comes from the included files.
+    pub fn sources(&self) -> HashMap<String, String> {
+        self.effective_lines()
+            .iter()
+            .filter(|it| it.file != self.file)
+            .filter_map(|it| dotenv::parse_line(&it.text).map(|(key, _)| (key, it.file.clone())))
+            .collect()
+    }
+
+    // The lines without the overridden definitions.
+    fn effective_lines(&self) -> Vec<&Line> {
+        let mut last = HashMap::new();
+        for (index, it) in self.lines.iter().enumerate() {
+            if let Some((key, _)) = dotenv::parse_line(&it.text) {
+                last.insert(key, index);
+            }
+        }
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(index, it)| match dotenv::parse_line(&it.text) {
+                Some((key, _)) => last[&key] == *index,
+                None => true,
+            })
+            .map(|(_, it)| it)
+            .collect()
+    }
+}
+
+// The path of the included file, for example `#@include .env.shared`, the space after `#` is
+// allowed like the annotations in template.
+pub fn parse_include(line: &str) -> Option<&str> {
+    let directive = line.trim().strip_prefix('#')?.trim_start();
+    let path = directive.strip_prefix("@include")?;
+    if path.starts_with(char::is_whitespace) && !path.trim().is_empty() {
+        Some(path.trim())
+    } else {
+        None
+    }
+}
+
+// Expand the includes recursively, the included files are read by `read` with the path relative
+// to the repository, and they cannot be outside of it.
+pub fn compose(
+    file: &str,
+    content: &str,
+    read: &dyn Fn(&str) -> Result<String, String>,
+) -> Result<Composition, String> {
+    let mut stack = vec![file.to_string()];
+    let mut includes = vec![];
+    let lines = expand(file, content, read, &mut stack, &mut includes)?;
+    Ok(Composition {
+        file: file.to_string(),
+        lines,
+        includes,
+        trailing_newline: content.is_empty() || content.ends_with('\n'),
+    })
+}
+
+fn expand(
+    file: &str,
+    content: &str,
+    read: &dyn Fn(&str) -> Result<String, String>,
+    stack: &mut Vec<String>,
+    includes: &mut Vec<String>,
+) -> Result<Vec<Line>, String> {
+    let mut lines = vec![];
+    for (index, text) in content.lines().enumerate() {
+        let path = match parse_include(text) {
+            Some(it) => it,
+            None => {
+                lines.push(Line {
+                    text: text.to_string(),
+                    file: file.to_string(),
+                    line: index + 1,
+                });
+                continue;
+            }
+        };
+        let is_inside = Path::new(path)
+            .components()
+            .all(|it| matches!(it, Component::Normal(_) | Component::CurDir));
+        // `./.env.shared` is the same file as `.env.shared`, which matters to find the cycles.
+        let included = &normalize(path);
+        if !is_inside || included.is_empty() {
+            return Err(format!(
+                "{}:{}: cannot include {} outside of the repository",
+                file,
+                index + 1,
+                path
+            ));
+        }
+        if stack.iter().any(|it| it == included) {
+            let mut cycle = stack.clone();
+            cycle.push(included.to_string());
+            return Err(format!("cyclic include {}", cycle.join(" -> ")));
+        }
+        let included_content =
+            read(included).map_err(|err| format!("{}:{}: {}", file, index + 1, err))?;
+        if !includes.contains(included) {
+            includes.push(included.to_string());
+        }
+        stack.push(included.to_string());
+        lines.extend(expand(included, &included_content, read, stack, includes)?);
+        stack.pop();
+    }
+    Ok(lines)
+}
+
+fn normalize(path: &str) -> String {
+    Path::new(path)
+        .components()
+        .filter_map(|it| match it {
+            Component::Normal(it) => it.to_str(),
+            _ => None,
+        })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(path: &str) -> Result<String, String> {
+        match path {
+            ".env.shared" => Ok(String::from("#@include .env.base\nLOG=info\nPORT=80\n")),
+            ".env.base" => Ok(String::from("# base\nREGION=eu\nPORT=8080\n")),
+            ".env.a" => Ok(String::from("# @include .env.b\n")),
+            ".env.b" => Ok(String::from("#@include .env.a\n")),
+            _ => Err(format!("cannot read {}", path)),
+        }
+    }
+
+    #[test]
+    fn should_parse_include() {
+        assert_eq!(parse_include("#@include .env.shared"), Some(".env.shared"));
+        assert_eq!(
+            parse_include("  # @include  shared/.env "),
+            Some("shared/.env")
+        );
+        assert_eq!(parse_include("#@include"), None);
+        assert_eq!(parse_include("#@included .env"), None);
+        assert_eq!(parse_include("KEY=#@include .env"), None);
+    }
+
+    #[test]
+    fn should_compose_includes() -> Result<(), String> {
+        let composition = compose(
+            ".env.dev",
+            "#@include .env.shared\nPORT=3000\nDEBUG=true\n",
+            &read,
+        )?;
+
+        assert!(composition.is_composed());
+        assert_eq!(composition.includes(), [".env.shared", ".env.base"]);
+        assert_eq!(
+            composition.content(),
+            "# base\nREGION=eu\nLOG=info\nPORT=3000\nDEBUG=true\n"
+        );
+        let sources = composition.sources();
+        assert_eq!(sources["REGION"], ".env.base");
+        assert_eq!(sources["LOG"], ".env.shared");
+        assert!(!sources.contains_key("PORT"));
+        let lines: Vec<(String, usize)> = composition
+            .entries()
+            .into_iter()
+            .map(|it| (it.key, it.line))
+            .collect();
+        assert_eq!(lines[0], (String::from("REGION"), 2));
+        assert_eq!(lines[2], (String::from("PORT"), 2));
+        Ok(())
+    }
+
+    #[test]
+    fn should_merge_compositions() -> Result<(), String> {
+        let base = compose(".env.common", "#@include .env.shared\nENV=base\n", &read)?;
+        let production = compose(".env.production", "ENV=production\nLOG=warn\n", &read)?;
+
+        let merged = Composition::merge(vec![base, production]);
+
+        assert_eq!(
+            merged.content(),
+            "# base\nREGION=eu\nPORT=80\nENV=production\nLOG=warn\n"
+        );
+        assert_eq!(merged.sources()["PORT"], ".env.shared");
+        assert_eq!(merged.includes(), [".env.shared", ".env.base"]);
+        let files: Vec<(String, String)> = merged
+            .definitions("PORT")
+            .into_iter()
+            .map(|it| (it.file, it.value))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                (String::from(".env.base"), String::from("8080")),
+                (String::from(".env.shared"), String::from("80")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_include_errors() {
+        let err = compose(".env.dev", "#@include .env.a\n", &read).unwrap_err();
+        assert_eq!(err, "cyclic include .env.dev -> .env.a -> .env.b -> .env.a");
+        assert!(compose(".env.dev", "#@include ../.env\n", &read).is_err());
+        assert!(compose(".env.dev", "#@include /etc/passwd\n", &read).is_err());
+        assert!(compose(".env.dev", "#@include ./.env.dev\n", &read)
+            .unwrap_err()
+            .starts_with("cyclic include"));
+        assert!(compose(".env.dev", "#@include .env.missing\n", &read)
+            .unwrap_err()
+            .starts_with(".env.dev:1:"));
+    }
+
+    #[test]
+    fn should_keep_content_without_includes() -> Result<(), String> {
+        let composition = compose(".env.dev", "# dev\nA=1\nA=2", &read)?;
+        assert!(!composition.is_composed());
+        assert!(composition.includes().is_empty());
+        assert_eq!(composition.content(), "# dev\nA=2");
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
+use crate::repository::environment::EnvType;
 use crate::repository::Repository;
+use regex::Regex;
 use std::path::{Component, Path, PathBuf};
 
 pub fn get_config_path<P: AsRef<Path>>(path: P) -> PathBuf {
@@ -20,18 +22,105 @@ pub fn get_env_path(repo: &Repository, env: &str) -> PathBuf {
     if !is_valid_env(env) {
         panic!("The target environment is invalid: {}", env);
     }
-    let filename = repo.config.pattern().replace("{}", env);
+    let filename = render(repo.config.pattern(), &repo.config.dimensions(), env);
     repo.path.join(filename)
 }
 
+// Replace the placeholders with the environment, the environment of the named placeholders is
+// their values joined by `.` in order, like `api.staging` of `.env.{service}.{stage}`. The
+// placeholders missing from the text are skipped, so the local can have some of them.
+pub fn render(text: &str, dimensions: &[String], env: &str) -> String {
+    if dimensions.is_empty() {
+        return text.replace("{}", env);
+    }
+    let mut rendered = String::from(text);
+    for (dimension, value) in dimensions.iter().zip(env.splitn(dimensions.len(), '.')) {
+        rendered = rendered.replace(&format!("{{{}}}", dimension), value);
+    }
+    rendered
+}
+
+// The environment of the named placeholders has a non-empty value for each of them, otherwise it
+// would be rendered to a file which is never listed, like `.env.api.{stage}` of `api`.
+pub fn has_all_dimensions(repo: &Repository, env: &str) -> bool {
+    let dimensions = repo.config.dimensions();
+    if dimensions.is_empty() {
+        return true;
+    }
+    let values: Vec<&str> = env.split('.').collect();
+    values.len() == dimensions.len()
+        && values.iter().all(|it| !it.is_empty())
+        && !Regex::new(r"\{[a-z_]*\}").unwrap().is_match(&render(
+            repo.config.pattern(),
+            &dimensions,
+            env,
+        ))
+}
+
+// The glob matching every file of the pattern, used by `.gitignore`.
+pub fn to_glob(text: &str) -> String {
+    Regex::new(r"\{[a-z_]*\}")
+        .unwrap()
+        .replace_all(text, "*")
+        .to_string()
+}
+
+pub fn get_encrypted_env_path(repo: &Repository, env: &str) -> PathBuf {
+    let mut env_path = get_env_path(repo, env).into_os_string();
+    env_path.push(".enc");
+    PathBuf::from(env_path)
+}
+
+// The local environment is stored at the file configured by `local` instead of the pattern.
+pub fn get_env_or_local_path(repo: &Repository, env: &str) -> PathBuf {
+    match EnvType::from(env) {
+        EnvType::Local => get_local_env_path(repo),
+        EnvType::Other(_) | EnvType::Stack(_) | EnvType::Selection(_) => get_env_path(repo, env),
+    }
+}
+
 pub fn get_local_backup_path(repo: &Repository) -> PathBuf {
     get_envm_path(&repo.path).join(".env.backup")
 }
 
+pub fn get_backups_path(repo: &Repository) -> PathBuf {
+    get_envm_path(&repo.path).join("backups")
+}
+
+pub fn get_recipients_path(repo: &Repository) -> PathBuf {
+    get_envm_path(&repo.path).join("recipients")
+}
+
+pub fn get_audit_log_path(repo: &Repository) -> PathBuf {
+    get_envm_path(&repo.path).join("audit.log")
+}
+
+pub fn get_salt_path(repo: &Repository) -> PathBuf {
+    get_envm_path(&repo.path).join("salt")
+}
+
+pub fn get_signatures_path(repo: &Repository) -> PathBuf {
+    get_envm_path(&repo.path).join("signatures")
+}
+
+pub fn get_schema_path(repo: &Repository) -> PathBuf {
+    match repo.config.schema() {
+        Some(schema) => repo.path.join(schema),
+        None => get_envm_path(&repo.path).join("schema.json"),
+    }
+}
+
 pub fn get_local_env_path(repo: &Repository) -> PathBuf {
     repo.path.join(repo.config.local())
 }
 
+// The local file the environment is used at, which is different for each service when the local
+// has the placeholders.
+pub fn get_local_env_path_of(repo: &Repository, env: &str) -> PathBuf {
+    let rendered = render(repo.config.local(), &repo.config.dimensions(), env);
+    repo.path.join(rendered)
+}
+
 pub fn get_template_env_path(repo: &Repository) -> PathBuf {
     repo.path.join(repo.config.template())
 }
@@ -69,6 +158,23 @@ mod tests {
         assert_eq!(get_env_path(&repo, env), Path::new("/repo/.env.dev"));
     }
 
+    #[test]
+    fn should_render_named_placeholders() {
+        let dimensions = vec![String::from("service"), String::from("stage")];
+        assert_eq!(
+            render(".env.{service}.{stage}", &dimensions, "api.staging"),
+            ".env.api.staging"
+        );
+        assert_eq!(
+            render(".env.{service}", &dimensions, "api.staging"),
+            ".env.api"
+        );
+        assert_eq!(render(".env", &dimensions, "api.staging"), ".env");
+        assert_eq!(render(".env.{}", &[], "dev"), ".env.dev");
+        assert_eq!(to_glob(".env.{service}.{stage}"), ".env.*.*");
+        assert_eq!(to_glob(".env.{}"), ".env.*");
+    }
+
     #[test]
     #[should_panic]
     fn should_not_get_env_path_out_of_repo() {
@@ -77,6 +183,19 @@ mod tests {
         get_env_path(&repo, env);
     }
 
+    #[test]
+    fn should_get_env_or_local_path() {
+        let repo = Repository::new(Path::new("/repo").to_path_buf());
+        assert_eq!(
+            get_env_or_local_path(&repo, "dev"),
+            Path::new("/repo/.env.dev")
+        );
+        assert_eq!(
+            get_env_or_local_path(&repo, "local"),
+            Path::new("/repo/.env")
+        );
+    }
+
     #[test]
     fn should_get_local_backup_path() {
         let repo = Repository::new(Path::new("/repo").to_path_buf());
@@ -86,6 +205,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn should_get_backups_path() {
+        let repo = Repository::new(Path::new("/repo").to_path_buf());
+        assert_eq!(get_backups_path(&repo), Path::new("/repo/.envm/backups"));
+    }
+
+    #[test]
+    fn should_get_encrypted_env_path() {
+        let repo = Repository::new(Path::new("/repo").to_path_buf());
+        assert_eq!(
+            get_encrypted_env_path(&repo, "production"),
+            Path::new("/repo/.env.production.enc")
+        );
+    }
+
+    #[test]
+    fn should_get_recipients_path() {
+        let repo = Repository::new(Path::new("/repo").to_path_buf());
+        assert_eq!(
+            get_recipients_path(&repo),
+            Path::new("/repo/.envm/recipients")
+        );
+    }
+
+    #[test]
+    fn should_get_audit_log_path() {
+        let repo = Repository::new(Path::new("/repo").to_path_buf());
+        assert_eq!(
+            get_audit_log_path(&repo),
+            Path::new("/repo/.envm/audit.log")
+        );
+    }
+
+    #[test]
+    fn should_get_signatures_path() {
+        let repo = Repository::new(Path::new("/repo").to_path_buf());
+        assert_eq!(
+            get_signatures_path(&repo),
+            Path::new("/repo/.envm/signatures")
+        );
+    }
+
+    #[test]
+    fn should_get_schema_path() {
+        let repo = Repository::new(Path::new("/repo").to_path_buf());
+        assert_eq!(get_schema_path(&repo), Path::new("/repo/.envm/schema.json"));
+    }
+
     #[test]
     fn should_get_envm_path() {
         let repo = Repository::new(Path::new("/repo").to_path_buf());
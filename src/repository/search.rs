@@ -0,0 +1,43 @@
+use regex::Regex;
+
+// Which part of the variables should be matched against the pattern.
+#[derive(Debug, Clone, Copy)]
+pub enum SearchScope {
+    Keys,
+    Values,
+    All,
+}
+
+impl SearchScope {
+    pub fn is_match(&self, re: &Regex, key: &str, value: &str) -> bool {
+        match self {
+            SearchScope::Keys => re.is_match(key),
+            SearchScope::Values => re.is_match(value),
+            SearchScope::All => re.is_match(key) || re.is_match(value),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SearchMatch {
+    pub source: String,
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_by_scope() {
+        let re = Regex::new("db").unwrap();
+        assert!(SearchScope::Keys.is_match(&re, "db_host", "localhost"));
+        assert!(!SearchScope::Keys.is_match(&re, "HOST", "db.local"));
+        assert!(SearchScope::Values.is_match(&re, "HOST", "db.local"));
+        assert!(!SearchScope::Values.is_match(&re, "db_host", "localhost"));
+        assert!(SearchScope::All.is_match(&re, "HOST", "db.local"));
+        assert!(SearchScope::All.is_match(&re, "db_host", "localhost"));
+    }
+}
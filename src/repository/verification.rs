@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use crate::configuration::interpolation::References;
+use crate::configuration::validation::Issue;
+use crate::repository::config::Strictness;
+
+// The result of checking an environment against the template.
+#[derive(Debug, Default)]
+pub struct Verification {
+    // The name of the environment file, used to report where the invalid values are.
+    pub file: String,
+    pub missing: Vec<String>,
+    pub extra: Vec<String>,
+    pub empty_required: Vec<String>,
+    pub invalid: Vec<Issue>,
+    // The variables still holding the placeholder from template.
+    pub placeholders: Vec<String>,
+    // The file doesn't match its signature, which means it's modified outside envm.
+    pub tampered: bool,
+    // The environments are signed but not this one.
+    pub unsigned: bool,
+    // The included file of each variable which doesn't come from the environment file itself.
+    pub sources: HashMap<String, String>,
+    // The variables each invalid value is built from by interpolation, with their values.
+    pub references: HashMap<String, References>,
+}
+
+impl Verification {
+    // The extra variables won't break the program, so they are not treated as problems.
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty()
+            && self.empty_required.is_empty()
+            && self.invalid.is_empty()
+            && !self.tampered
+    }
+
+    pub fn is_clean(&self) -> bool {
+        self.is_ok() && self.extra.is_empty() && self.placeholders.is_empty() && !self.unsigned
+    }
+
+    // The file where the variable is defined.
+    pub fn source(&self, key: &str) -> &str {
+        self.sources.get(key).unwrap_or(&self.file)
+    }
+
+    pub fn references(&self, key: &str) -> &[(String, String)] {
+        self.references
+            .get(key)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    // Whether the environment can be used under the strictness.
+    pub fn is_acceptable(&self, strictness: Strictness) -> bool {
+        match strictness {
+            Strictness::Lax => true,
+            Strictness::Normal => self.is_ok(),
+            Strictness::Strict => self.is_clean(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_ignore_extra_variables() {
+        let verification = Verification {
+            extra: vec![String::from("DEBUG")],
+            ..Verification::default()
+        };
+        assert!(verification.is_ok());
+        assert!(verification.is_acceptable(Strictness::Normal));
+        assert!(!verification.is_acceptable(Strictness::Strict));
+    }
+
+    #[test]
+    fn should_not_be_clean_with_placeholders() {
+        let verification = Verification {
+            placeholders: vec![String::from("API_KEY")],
+            ..Verification::default()
+        };
+        assert!(verification.is_ok());
+        assert!(!verification.is_clean());
+    }
+
+    #[test]
+    fn should_not_be_ok_with_invalid_values() {
+        let verification = Verification {
+            invalid: vec![Issue {
+                line: 1,
+                key: String::from("PORT"),
+                value: String::from("abc"),
+                message: String::from("expected an integer"),
+            }],
+            ..Verification::default()
+        };
+        assert!(!verification.is_ok());
+    }
+
+    #[test]
+    fn should_not_be_ok_when_tampered() {
+        let verification = Verification {
+            tampered: true,
+            ..Verification::default()
+        };
+        assert!(!verification.is_ok());
+        assert!(verification.is_acceptable(Strictness::Lax));
+    }
+
+    #[test]
+    fn should_not_be_ok_with_missing_variables() {
+        let verification = Verification {
+            missing: vec![String::from("APP_URL")],
+            ..Verification::default()
+        };
+        assert!(!verification.is_ok());
+        assert!(verification.is_acceptable(Strictness::Lax));
+    }
+}
@@ -0,0 +1,197 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+const HEADER: &str = "envm-signatures v1";
+
+// The key is kept outside of the repository, so whoever can only write the synced folder cannot
+// sign the files again.
+pub const KEY_FILE_VARIABLE: &str = "ENVM_SIGNING_KEY_FILE";
+
+// The HMAC-SHA256 of each environment file keyed by the filename, the manifest itself is signed
+// as well so the entries cannot be removed or swapped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Manifest {
+    signatures: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+    Valid,
+    // The file is changed since it was signed.
+    Modified,
+    Unsigned,
+}
+
+impl Manifest {
+    pub fn parse(content: &str, key: &[u8]) -> Result<Manifest, String> {
+        let mut lines = content.lines().map(str::trim).filter(|it| !it.is_empty());
+        if lines.next() != Some(HEADER) {
+            return Err(format!("expected `{}` at the first line", HEADER));
+        }
+        let mut mac = None;
+        let mut signatures = BTreeMap::new();
+        for line in lines {
+            match line.split_once(' ') {
+                Some(("mac", value)) => mac = Some(value.trim().to_string()),
+                Some(("file", value)) => match value.trim().rsplit_once(' ') {
+                    Some((file, signature)) => {
+                        signatures.insert(file.to_string(), signature.to_string());
+                    }
+                    None => return Err(format!("invalid line `{}`", line)),
+                },
+                _ => return Err(format!("invalid line `{}`", line)),
+            }
+        }
+        let manifest = Manifest { signatures };
+        if mac.as_deref() != Some(manifest.mac(key).as_str()) {
+            return Err(String::from(
+                "the signatures are modified or signed by another key",
+            ));
+        }
+        Ok(manifest)
+    }
+
+    pub fn sign(&mut self, key: &[u8], file: &str, content: &[u8]) {
+        self.signatures
+            .insert(file.to_string(), hmac(key, &[file.as_bytes(), content]));
+    }
+
+    pub fn remove(&mut self, file: &str) {
+        self.signatures.remove(file);
+    }
+
+    pub fn check(&self, key: &[u8], file: &str, content: &[u8]) -> Status {
+        match self.signatures.get(file) {
+            Some(signature) if *signature == hmac(key, &[file.as_bytes(), content]) => {
+                Status::Valid
+            }
+            Some(_) => Status::Modified,
+            None => Status::Unsigned,
+        }
+    }
+
+    // The content of the manifest file, which needs the key to sign the manifest.
+    pub fn seal(&self, key: &[u8]) -> String {
+        let mut content = format!("{}\nmac {}\n", HEADER, self.mac(key));
+        for (file, signature) in &self.signatures {
+            content.push_str(&format!("file {} {}\n", file, signature));
+        }
+        content
+    }
+
+    fn mac(&self, key: &[u8]) -> String {
+        let lines: Vec<String> = self
+            .signatures
+            .iter()
+            .map(|(file, signature)| format!("{} {}", file, signature))
+            .collect();
+        hmac(key, &[HEADER.as_bytes(), lines.join("\n").as_bytes()])
+    }
+}
+
+// The parts are separated by NUL so that moving bytes between them changes the signature.
+fn hmac(key: &[u8], parts: &[&[u8]]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    for (index, it) in parts.iter().enumerate() {
+        if index > 0 {
+            mac.update(&[0u8]);
+        }
+        mac.update(it);
+    }
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|it| format!("{:02x}", it))
+        .collect()
+}
+
+fn key_path() -> Result<PathBuf, String> {
+    match env::var(KEY_FILE_VARIABLE) {
+        Ok(it) => Ok(PathBuf::from(it)),
+        Err(_) => {
+            let home =
+                env::var("HOME").map_err(|_| String::from("cannot found the home directory"))?;
+            Ok(Path::new(&home).join(".config/envm/signing.key"))
+        }
+    }
+}
+
+// Read the key from `ENVM_SIGNING_KEY_FILE`, default to `~/.config/envm/signing.key`, a random key
+// is generated when `create` is set and the file doesn't exist. The file can be copied to the
+// people who should be able to sign.
+pub fn read_key(create: bool) -> Result<Vec<u8>, String> {
+    let key_path = key_path()?;
+    if create && !key_path.exists() {
+        let mut key = [0u8; 32];
+        getrandom::getrandom(&mut key).map_err(|err| err.to_string())?;
+        if let Some(dir) = key_path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&key_path)
+            .map_err(|err| format!("cannot create {}: {}", key_path.display(), err))?;
+        writeln!(file, "{}", STANDARD.encode(key)).map_err(|err| err.to_string())?;
+    }
+    let content = fs::read_to_string(&key_path)
+        .map_err(|err| format!("cannot read {}: {}", key_path.display(), err))?;
+    STANDARD
+        .decode(content.trim())
+        .map_err(|err| format!("invalid key in {}: {}", key_path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_check_signatures() {
+        let mut manifest = Manifest::default();
+        manifest.sign(b"key", ".env.dev", b"ENV=dev\n");
+
+        assert_eq!(
+            manifest.check(b"key", ".env.dev", b"ENV=dev\n"),
+            Status::Valid
+        );
+        assert_eq!(
+            manifest.check(b"key", ".env.dev", b"ENV=prod\n"),
+            Status::Modified
+        );
+        assert_eq!(
+            manifest.check(b"other", ".env.dev", b"ENV=dev\n"),
+            Status::Modified
+        );
+        assert_eq!(
+            manifest.check(b"key", ".env.qa", b"ENV=dev\n"),
+            Status::Unsigned
+        );
+    }
+
+    #[test]
+    fn should_not_parse_tampered_manifest() -> Result<(), String> {
+        let mut manifest = Manifest::default();
+        manifest.sign(b"key", ".env.dev", b"ENV=dev\n");
+        manifest.sign(b"key", ".env.qa", b"ENV=qa\n");
+        let sealed = manifest.seal(b"key");
+
+        assert_eq!(Manifest::parse(&sealed, b"key")?, manifest);
+        assert!(Manifest::parse(&sealed, b"other").is_err());
+        let removed: Vec<&str> = sealed
+            .lines()
+            .filter(|it| !it.starts_with("file .env.qa"))
+            .collect();
+        assert!(Manifest::parse(&removed.join("\n"), b"key").is_err());
+        assert!(Manifest::parse("", b"key").is_err());
+        Ok(())
+    }
+}